@@ -0,0 +1,469 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+// Sign and magnitude, the magnitude in base 2^32 with the least significant limb first and
+// no zero limbs on top, so zero is an empty magnitude and is never negative
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    mag: Vec<u32>
+}
+
+fn trim(mag: &mut Vec<u32>) {
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+
+    for i in 0..a.len().max(b.len()) {
+        let sum = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        res.push(sum as u32);
+        carry = sum >> 32;
+    }
+
+    if carry > 0 {
+        res.push(carry as u32);
+    }
+
+    res
+}
+
+// a must not be smaller than b
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut res = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+
+    for (i, &x) in a.iter().enumerate() {
+        let mut diff = x as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if diff < 0 {
+            diff += 1 << 32;
+            borrow = 1;
+        }
+        res.push(diff as u32);
+    }
+
+    trim(&mut res);
+    res
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return Vec::new();
+    }
+
+    let mut res = vec![0u32; a.len() + b.len()];
+
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+
+        for (j, &y) in b.iter().enumerate() {
+            let cur = res[i + j] as u64 + x as u64 * y as u64 + carry;
+            res[i + j] = cur as u32;
+            carry = cur >> 32;
+        }
+
+        res[i + b.len()] = carry as u32;
+    }
+
+    trim(&mut res);
+    res
+}
+
+fn divrem_small(a: &[u32], d: u32) -> (Vec<u32>, u32) {
+    let mut quot = vec![0u32; a.len()];
+    let mut rem = 0u64;
+
+    for i in (0..a.len()).rev() {
+        let cur = (rem << 32) | a[i] as u64;
+        quot[i] = (cur / d as u64) as u32;
+        rem = cur % d as u64;
+    }
+
+    trim(&mut quot);
+    (quot, rem as u32)
+}
+
+// Schoolbook long division one bit at a time, slow for huge divisors but simple to trust
+fn divrem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_mag(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+
+    if b.len() == 1 {
+        let (quot, rem) = divrem_small(a, b[0]);
+        return (quot, if rem == 0 { Vec::new() } else { vec![rem] });
+    }
+
+    let mut quot = vec![0u32; a.len()];
+    let mut rem = Vec::new();
+
+    for i in (0..a.len() * 32).rev() {
+        rem = shl_mag(&rem, 1);
+        if (a[i / 32] >> (i % 32)) & 1 == 1 {
+            if rem.is_empty() {
+                rem.push(1);
+            } else {
+                rem[0] |= 1;
+            }
+        }
+
+        if cmp_mag(&rem, b) != Ordering::Less {
+            rem = sub_mag(&rem, b);
+            quot[i / 32] |= 1 << (i % 32);
+        }
+    }
+
+    trim(&mut quot);
+    (quot, rem)
+}
+
+fn shl_mag(a: &[u32], bits: usize) -> Vec<u32> {
+    if a.is_empty() {
+        return Vec::new();
+    }
+
+    let mut res = vec![0u32; bits / 32];
+    let shift = bits % 32;
+    let mut carry = 0u32;
+
+    for &x in a {
+        if shift == 0 {
+            res.push(x);
+        } else {
+            res.push((x << shift) | carry);
+            carry = x >> (32 - shift);
+        }
+    }
+
+    if carry > 0 {
+        res.push(carry);
+    }
+
+    res
+}
+
+impl BigInt {
+    fn new(negative: bool, mut mag: Vec<u32>) -> BigInt {
+        trim(&mut mag);
+        BigInt { negative: negative && !mag.is_empty(), mag }
+    }
+
+    // Decimal digits with an optional leading sign
+    pub fn parse(text: &str) -> Option<BigInt> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(text))
+        };
+
+        if digits.is_empty() || !digits.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        let mut mag = Vec::new();
+        for chunk in digits.as_bytes().chunks(9) {
+            let chunk = std::str::from_utf8(chunk).unwrap();
+            let scale = BigInt::from(10i128.pow(chunk.len() as u32));
+            let value = BigInt::from(chunk.parse::<i128>().unwrap());
+            mag = add_mag(&mul_mag(&mag, &scale.mag), &value.mag);
+        }
+
+        Some(BigInt::new(negative, mag))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn to_i128(&self) -> Option<i128> {
+        if self.mag.len() > 4 {
+            return None;
+        }
+
+        let mag = self.low_bits();
+        if self.negative {
+            if mag <= 1 << 127 { Some((mag as i128).wrapping_neg()) } else { None }
+        } else {
+            i128::try_from(mag).ok()
+        }
+    }
+
+    // What two's complement arithmetic on i128 would have left behind
+    pub fn wrap_i128(&self) -> i128 {
+        let mag = self.low_bits();
+        if self.negative { mag.wrapping_neg() as i128 } else { mag as i128 }
+    }
+
+    fn low_bits(&self) -> u128 {
+        self.mag.iter().take(4).rev().fold(0u128, |acc, &limb| (acc << 32) | limb as u128)
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let mag = self.mag.iter().rev().fold(0f64, |acc, &limb| acc * 4294967296.0 + limb as f64);
+        if self.negative { -mag } else { mag }
+    }
+
+    // Drops the fraction, there is no big int for INF or NAN
+    pub fn from_f64(n: f64) -> Option<BigInt> {
+        if !n.is_finite() {
+            return None;
+        }
+
+        let n = n.trunc();
+        if n.abs() < 1.0 {
+            return Some(BigInt::from(0));
+        }
+
+        let bits = n.abs().to_bits();
+        let exp = ((bits >> 52) & 0x7ff) as i64 - 1075;
+        let mant = (bits & ((1 << 52) - 1)) | (1 << 52);
+
+        let mag = if exp < 0 {
+            BigInt::from((mant >> -exp) as i128).mag
+        } else {
+            shl_mag(&BigInt::from(mant as i128).mag, exp as usize)
+        };
+
+        Some(BigInt::new(n < 0.0, mag))
+    }
+
+    // Exact, where to_f64 would round this; None when n is NaN
+    pub fn cmp_f64(&self, n: f64) -> Option<Ordering> {
+        if n.is_nan() {
+            return None;
+        }
+        let whole = match BigInt::from_f64(n) {
+            Some(whole) => whole,
+            None if n > 0.0 => return Some(Ordering::Less),
+            None => return Some(Ordering::Greater)
+        };
+
+        // Same whole part, so the fraction left over decides
+        Some(self.cmp(&whole).then_with(|| 0.0.partial_cmp(&n.fract()).unwrap()))
+    }
+
+    pub fn add(&self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add_mag(&self.mag, &other.mag));
+        }
+
+        match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => BigInt::new(other.negative, sub_mag(&other.mag, &self.mag)),
+            _ => BigInt::new(self.negative, sub_mag(&self.mag, &other.mag))
+        }
+    }
+
+    pub fn neg(&self) -> BigInt {
+        BigInt::new(!self.negative, self.mag.clone())
+    }
+
+    pub fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &BigInt) -> BigInt {
+        BigInt::new(self.negative != other.negative, mul_mag(&self.mag, &other.mag))
+    }
+
+    // Rounds toward zero like i128 does, the remainder takes the sign of self
+    pub fn div_rem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+
+        let (quot, rem) = divrem_mag(&self.mag, &other.mag);
+        Some((BigInt::new(self.negative != other.negative, quot), BigInt::new(self.negative, rem)))
+    }
+}
+
+impl From<i128> for BigInt {
+    fn from(n: i128) -> BigInt {
+        let mag = n.unsigned_abs();
+        BigInt::new(n < 0, (0..4).map(|i| (mag >> (32 * i)) as u32).collect())
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag)
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        // Peel off nine decimal digits at a time, least significant first
+        let mut chunks = Vec::new();
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            let (quot, rem) = divrem_small(&mag, 1_000_000_000);
+            chunks.push(rem);
+            mag = quot;
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{chunk:09}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(text: &str) -> BigInt {
+        BigInt::parse(text).unwrap()
+    }
+
+    fn div_rem(x: &str, y: &str) -> (String, String) {
+        let (quot, rem) = big(x).div_rem(&big(y)).unwrap();
+        (quot.to_string(), rem.to_string())
+    }
+
+    #[test]
+    fn i128_bounds_round_trip() {
+        for n in [0, 1, -1, i64::MAX as i128 + 1, i128::MAX, i128::MIN, i128::MIN + 1] {
+            let x = BigInt::from(n);
+            assert_eq!(x.to_i128(), Some(n));
+            assert_eq!(x.to_string(), n.to_string());
+            assert_eq!(big(&n.to_string()), x);
+        }
+    }
+
+    #[test]
+    fn just_outside_i128_does_not_fit() {
+        let above = BigInt::from(i128::MAX).add(&BigInt::from(1));
+        let below = BigInt::from(i128::MIN).sub(&BigInt::from(1));
+
+        assert_eq!(above.to_string(), "170141183460469231731687303715884105728");
+        assert_eq!(below.to_string(), "-170141183460469231731687303715884105729");
+        assert_eq!(above.to_i128(), None);
+        assert_eq!(below.to_i128(), None);
+        assert_eq!(above.wrap_i128(), i128::MIN);
+        assert_eq!(below.wrap_i128(), i128::MAX);
+        // 2^127 fits only as a negative number
+        assert_eq!(above.neg().to_i128(), Some(i128::MIN));
+    }
+
+    #[test]
+    fn min_divided_by_minus_one_leaves_i128() {
+        let (quot, rem) = BigInt::from(i128::MIN).div_rem(&BigInt::from(-1)).unwrap();
+        assert_eq!(quot.to_string(), "170141183460469231731687303715884105728");
+        assert!(rem.is_zero());
+        assert_eq!(quot.to_i128(), None);
+    }
+
+    #[test]
+    fn division_rounds_toward_zero() {
+        assert_eq!(div_rem("7", "2"), ("3".to_string(), "1".to_string()));
+        assert_eq!(div_rem("-7", "2"), ("-3".to_string(), "-1".to_string()));
+        assert_eq!(div_rem("7", "-2"), ("-3".to_string(), "1".to_string()));
+        assert_eq!(div_rem("-7", "-2"), ("3".to_string(), "-1".to_string()));
+        // A zero remainder or quotient is never negative
+        assert_eq!(div_rem("-6", "3"), ("-2".to_string(), "0".to_string()));
+        assert_eq!(div_rem("-1", "5"), ("0".to_string(), "-1".to_string()));
+        assert!(big("5").div_rem(&big("0")).is_none());
+    }
+
+    #[test]
+    fn division_by_a_multi_limb_divisor() {
+        let x = big("-340282366920938463463374607431768211457");
+        let y = big("18446744073709551617");
+        let (quot, rem) = x.div_rem(&y).unwrap();
+
+        assert_eq!(quot.to_string(), "-18446744073709551615");
+        assert_eq!(rem.to_string(), "-2");
+        assert_eq!(quot.mul(&y).add(&rem), x);
+    }
+
+    #[test]
+    fn parse_takes_an_optional_sign_and_digits_only() {
+        assert_eq!(big("+42").to_i128(), Some(42));
+        assert_eq!(big("-0"), BigInt::from(0));
+        assert!(!big("-0").is_negative());
+        assert_eq!(big("000123").to_i128(), Some(123));
+        assert_eq!(big("1234567890123456789").to_string(), "1234567890123456789");
+
+        for text in ["", "-", "+", "1_000", "12a", " 1", "--1", "1.5"] {
+            assert_eq!(BigInt::parse(text), None, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn from_f64_drops_the_fraction() {
+        let from = |n: f64| BigInt::from_f64(n).map(|x| x.to_string());
+
+        assert_eq!(from(2.9), Some("2".to_string()));
+        assert_eq!(from(-2.9), Some("-2".to_string()));
+        assert_eq!(from(-0.5), Some("0".to_string()));
+        assert_eq!(from(1e20), Some("100000000000000000000".to_string()));
+        assert_eq!(from(2f64.powi(127)), Some("170141183460469231731687303715884105728".to_string()));
+        assert_eq!(from(-(2f64.powi(200))).unwrap(), format!("-{}", from(2f64.powi(200)).unwrap()));
+        assert_eq!(from(f64::INFINITY), None);
+        assert_eq!(from(f64::NEG_INFINITY), None);
+        assert_eq!(from(f64::NAN), None);
+    }
+
+    #[test]
+    fn cmp_f64_is_exact() {
+        let int = |n: i128| BigInt::from(n);
+
+        assert_eq!(int(9007199254740993).cmp_f64(9007199254740992.0), Some(Ordering::Greater));
+        assert_eq!(int(9007199254740992).cmp_f64(9007199254740992.0), Some(Ordering::Equal));
+        assert_eq!(int(2).cmp_f64(2.5), Some(Ordering::Less));
+        assert_eq!(int(-2).cmp_f64(-2.5), Some(Ordering::Greater));
+        assert_eq!(int(0).cmp_f64(-0.5), Some(Ordering::Greater));
+        assert_eq!(int(i128::MAX).cmp_f64(f64::INFINITY), Some(Ordering::Less));
+        assert_eq!(int(i128::MIN).cmp_f64(f64::NEG_INFINITY), Some(Ordering::Greater));
+        assert_eq!(int(0).cmp_f64(f64::NAN), None);
+    }
+
+    #[test]
+    fn to_f64_and_back() {
+        let x = big("-100000000000000000000");
+        assert_eq!(x.to_f64(), -1e20);
+        assert_eq!(BigInt::from_f64(x.to_f64()).unwrap(), x);
+    }
+
+    #[test]
+    fn order_follows_sign_then_magnitude() {
+        let huge = "340282366920938463463374607431768211456";
+        let mut xs = [big("5"), big(&format!("-{huge}")), big("0"), big("-5"), big(huge)];
+        xs.sort();
+        let xs: Vec<String> = xs.iter().map(|x| x.to_string()).collect();
+        assert_eq!(xs, ["-340282366920938463463374607431768211456", "-5", "0", "5", "340282366920938463463374607431768211456"]);
+    }
+}
@@ -0,0 +1,262 @@
+use crate::expressions::BuiltinFn;
+use crate::value::{Value, Map, Key};
+use crate::bigint::BigInt;
+use crate::error::{ErrorKind, Result};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Builtins with this arity take any number of arguments
+pub const VARIADIC: usize = usize::MAX;
+
+// Every builtin with its arity, Program::new binds them all as globals
+pub const BUILTINS: &[(&str, usize, BuiltinFn)] = &[
+    ("print", VARIADIC, print),
+    ("len", 1, len),
+    ("push", 2, push),
+    ("pop", 1, pop),
+    ("insert", 3, insert),
+    ("remove", 2, remove),
+    ("keys", 1, keys),
+    ("values", 1, values),
+    ("substring", 3, substring),
+    ("char_at", 2, char_at),
+    ("contains", 2, contains),
+    ("trim", 1, trim),
+    ("replace", 3, replace),
+    ("upper", 1, upper),
+    ("lower", 1, lower),
+    ("split", 2, split),
+    ("join", 2, join),
+    ("to_text", 1, to_text),
+    ("to_int", 1, to_int),
+    ("to_float", 1, to_float),
+    ("round", 1, round),
+    ("floor", 1, floor)
+];
+
+// Errors have no span, the call expression puts its own on them
+
+fn text(arg: &Value) -> Result<&str> {
+    match arg {
+        Value::Text(text) => Ok(text),
+        arg => Err(ErrorKind::TypeMismatch { expected: "TEXT", found: arg.type_name() }.into())
+    }
+}
+
+fn list(arg: &Value) -> Result<&Rc<RefCell<Vec<Value>>>> {
+    match arg {
+        Value::List(list) => Ok(list),
+        arg => Err(ErrorKind::TypeMismatch { expected: "LIST", found: arg.type_name() }.into())
+    }
+}
+
+fn map(arg: &Value) -> Result<&Rc<RefCell<Map>>> {
+    match arg {
+        Value::Map(map) => Ok(map),
+        arg => Err(ErrorKind::TypeMismatch { expected: "MAP", found: arg.type_name() }.into())
+    }
+}
+
+fn int(arg: &Value) -> Result<i128> {
+    match arg {
+        Value::Int(n) => Ok(*n),
+        Value::Big(n) => Err(ErrorKind::IntTooLarge(n.to_string()).into()),
+        arg => Err(ErrorKind::TypeMismatch { expected: "INT", found: arg.type_name() }.into())
+    }
+}
+
+// Positions count characters or items, a bound may sit one past the last one
+pub(crate) fn position(index: i128, len: usize, bound: bool) -> Result<usize> {
+    let limit = if bound { len + 1 } else { len };
+
+    match usize::try_from(index) {
+        Ok(i) if i < limit => Ok(i),
+        _ => Err(ErrorKind::IndexOutOfRange { index, len }.into())
+    }
+}
+
+// Text prints as is, everything else the way the REPL shows it
+fn show(arg: &Value) -> String {
+    match arg {
+        Value::Text(text) => text.to_string(),
+        arg => arg.string()
+    }
+}
+
+// Separated by spaces, so print() alone gives an empty line
+fn print(args: &[Value]) -> Result<Value> {
+    let items: Vec<String> = args.iter().map(show).collect();
+    println!("{}", items.join(" "));
+    Ok(Value::Unit)
+}
+
+fn len(args: &[Value]) -> Result<Value> {
+    match &args[0] {
+        Value::List(list) => Ok(Value::Int(list.borrow().len() as i128)),
+        Value::Map(map) => Ok(Value::Int(map.borrow().len() as i128)),
+        Value::Tuple(items) => Ok(Value::Int(items.len() as i128)),
+        arg => Ok(Value::Int(text(arg)?.chars().count() as i128))
+    }
+}
+
+// The list and map builtins change what they are given, which every copy of it sees
+
+fn push(args: &[Value]) -> Result<Value> {
+    list(&args[0])?.borrow_mut().push(args[1].clone());
+    Ok(Value::Unit)
+}
+
+fn pop(args: &[Value]) -> Result<Value> {
+    list(&args[0])?.borrow_mut().pop().ok_or_else(|| ErrorKind::EmptyList.into())
+}
+
+fn insert(args: &[Value]) -> Result<Value> {
+    let list = list(&args[0])?;
+    let index = position(int(&args[1])?, list.borrow().len(), true)?;
+    list.borrow_mut().insert(index, args[2].clone());
+    Ok(Value::Unit)
+}
+
+// Lists remove by position, maps by key
+fn remove(args: &[Value]) -> Result<Value> {
+    if let Value::Map(map) = &args[0] {
+        let key = Key::new(&args[1])?;
+        let value = map.borrow_mut().remove(&key);
+        return value.ok_or_else(|| ErrorKind::MissingKey(args[1].string()).into());
+    }
+
+    let list = list(&args[0])?;
+    let index = position(int(&args[1])?, list.borrow().len(), false)?;
+    let value = list.borrow_mut().remove(index);
+    Ok(value)
+}
+
+fn substring(args: &[Value]) -> Result<Value> {
+    let text = text(&args[0])?;
+    let len = text.chars().count();
+    let start = position(int(&args[1])?, len, true)?;
+    let end = position(int(&args[2])?, len, true)?;
+
+    if start > end {
+        return Err(ErrorKind::InvalidRange { start: start as i128, end: end as i128 }.into());
+    }
+
+    Ok(Value::Text(text.chars().skip(start).take(end - start).collect::<String>().into()))
+}
+
+fn char_at(args: &[Value]) -> Result<Value> {
+    let text = text(&args[0])?;
+    let index = position(int(&args[1])?, text.chars().count(), false)?;
+
+    Ok(Value::Text(text.chars().nth(index).unwrap().to_string().into()))
+}
+
+fn keys(args: &[Value]) -> Result<Value> {
+    let keys = map(&args[0])?.borrow().iter().map(|(key, _)| key.value()).collect();
+    Ok(Value::List(Rc::new(RefCell::new(keys))))
+}
+
+fn values(args: &[Value]) -> Result<Value> {
+    let values = map(&args[0])?.borrow().iter().map(|(_, value)| value.clone()).collect();
+    Ok(Value::List(Rc::new(RefCell::new(values))))
+}
+
+// Lists look for an item, maps for a key and text for a piece of text
+fn contains(args: &[Value]) -> Result<Value> {
+    match &args[0] {
+        Value::List(list) => Ok(Value::Bool(list.borrow().contains(&args[1]))),
+        Value::Map(map) => Ok(Value::Bool(map.borrow().get(&Key::new(&args[1])?).is_some())),
+        arg => Ok(Value::Bool(text(arg)?.contains(text(&args[1])?)))
+    }
+}
+
+fn trim(args: &[Value]) -> Result<Value> {
+    Ok(Value::Text(text(&args[0])?.trim().into()))
+}
+
+fn replace(args: &[Value]) -> Result<Value> {
+    Ok(Value::Text(text(&args[0])?.replace(text(&args[1])?, text(&args[2])?).into()))
+}
+
+fn upper(args: &[Value]) -> Result<Value> {
+    Ok(Value::Text(text(&args[0])?.to_uppercase().into()))
+}
+
+fn lower(args: &[Value]) -> Result<Value> {
+    Ok(Value::Text(text(&args[0])?.to_lowercase().into()))
+}
+
+// An empty separator splits text into its characters
+fn split(args: &[Value]) -> Result<Value> {
+    let whole = text(&args[0])?;
+    let items: Vec<Value> = match text(&args[1])? {
+        "" => whole.chars().map(|c| Value::Text(c.to_string().into())).collect(),
+        sep => whole.split(sep).map(|part| Value::Text(part.into())).collect()
+    };
+
+    Ok(Value::List(Rc::new(RefCell::new(items))))
+}
+
+// Items that are not text join the way to_text shows them
+fn join(args: &[Value]) -> Result<Value> {
+    let items: Vec<String> = list(&args[0])?.borrow().iter().map(show).collect();
+    Ok(Value::Text(items.join(text(&args[1])?).into()))
+}
+
+fn to_text(args: &[Value]) -> Result<Value> {
+    Ok(Value::Text(show(&args[0]).into()))
+}
+
+// INF and NAN have no int to become
+fn truncate(n: f64) -> Result<Value> {
+    match BigInt::from_f64(n) {
+        Some(n) => Ok(Value::big(n)),
+        None => Err(ErrorKind::InvalidConversion { text: Value::Float(n).string(), to: "INT" }.into())
+    }
+}
+
+// Ints go through untouched, floats are rounded toward zero
+fn to_int(args: &[Value]) -> Result<Value> {
+    match &args[0] {
+        Value::Int(_) | Value::Big(_) => Ok(args[0].clone()),
+        Value::Float(n) => truncate(*n),
+        arg => match BigInt::parse(text(arg)?.trim()) {
+            Some(n) => Ok(Value::big(n)),
+            None => Err(ErrorKind::InvalidConversion { text: arg.string(), to: "INT" }.into())
+        }
+    }
+}
+
+fn to_float(args: &[Value]) -> Result<Value> {
+    match &args[0] {
+        Value::Int(n) => Ok(Value::Float(*n as f64)),
+        Value::Big(n) => Ok(Value::Float(n.to_f64())),
+        Value::Float(n) => Ok(Value::Float(*n)),
+        arg => match text(arg)?.trim().parse() {
+            Ok(n) => Ok(Value::Float(n)),
+            Err(_) => Err(ErrorKind::InvalidConversion { text: arg.string(), to: "FLOAT" }.into())
+        }
+    }
+}
+
+fn number(arg: &Value) -> Result<f64> {
+    match arg {
+        Value::Float(n) => Ok(*n),
+        arg => Err(ErrorKind::TypeMismatch { expected: "NUMBER", found: arg.type_name() }.into())
+    }
+}
+
+// Halves round away from zero
+fn round(args: &[Value]) -> Result<Value> {
+    match &args[0] {
+        Value::Int(_) | Value::Big(_) => Ok(args[0].clone()),
+        arg => truncate(number(arg)?.round())
+    }
+}
+
+fn floor(args: &[Value]) -> Result<Value> {
+    match &args[0] {
+        Value::Int(_) | Value::Big(_) => Ok(args[0].clone()),
+        arg => truncate(number(arg)?.floor())
+    }
+}
@@ -0,0 +1,275 @@
+use crate::core::Frame;
+use crate::value::{Value, Slot, Map, Pointer};
+use crate::expressions::Function;
+use crate::records::Record;
+use std::collections::{HashMap, HashSet};
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc, Weak};
+
+type Vars = RefCell<HashMap<String, Value>>;
+
+// How many frames may be tracked before the first collection, later ones wait until twice
+// as many as survived the last are tracked
+const FIRST_COLLECTION: usize = 1000;
+
+// A function or pointer that captures a frame can be stored in that same frame, a cycle
+// Rc never frees. Frames are tracked once something captures them, and a collection
+// empties the ones only such cycles still hold
+#[derive(Default)]
+pub struct Collector {
+    frames: RefCell<HashMap<usize, Weak<Vars>>>,
+    limit: Cell<usize>
+}
+
+impl Collector {
+    pub fn track(&self, frame: &Frame) {
+        let mut frames = self.frames.borrow_mut();
+        let tracked = frames.entry(Rc::as_ptr(&frame.0) as usize).or_default();
+
+        // A freed frame's address can be reused by a new one
+        if tracked.strong_count() == 0 {
+            *tracked = Rc::downgrade(&frame.0);
+        }
+    }
+
+    // How many tracked frames are still alive
+    pub fn len(&self) -> usize {
+        self.frames.borrow().values().filter(|frame| frame.strong_count() > 0).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn collect_if_due(&self) {
+        if self.frames.borrow().len() >= self.limit.get().max(FIRST_COLLECTION) {
+            self.collect();
+        }
+    }
+
+    // Whatever holds a value from outside the values reachable from tracked frames, a Rust
+    // variable or the frames of a call in progress, shows in its reference count as more
+    // than is found inside them. That and everything it reaches is alive, and the tracked
+    // frames that are not are emptied, which frees their cycles
+    pub fn collect(&self) {
+        let roots: Vec<Rc<Vars>> = self.frames.borrow().values().filter_map(Weak::upgrade).collect();
+
+        // Each node with how many of its references come from outside
+        let mut nodes: HashMap<usize, (Node, usize)> = HashMap::new();
+        let mut work = Vec::new();
+
+        for frame in &roots {
+            let node = Node::Frame(Rc::clone(frame));
+            // Less the one in roots and the one in node
+            let outside = node.strong() - 2;
+            nodes.insert(node.address(), (node.clone(), outside));
+            work.push(node);
+        }
+
+        while let Some(node) = work.pop() {
+            let seen = node.edges(&mut |child| {
+                match nodes.get_mut(&child.address()) {
+                    Some((_, outside)) => *outside = outside.saturating_sub(1),
+                    None => {
+                        // Less the child itself and the reference just followed
+                        let outside = child.strong() - 2;
+                        nodes.insert(child.address(), (child.clone(), outside));
+                        work.push(child);
+                    }
+                }
+            });
+
+            // In use right now, so someone outside holds it
+            if !seen {
+                nodes.get_mut(&node.address()).unwrap().1 = usize::MAX;
+            }
+        }
+
+        let mut alive = HashSet::new();
+        let mut work: Vec<Node> = nodes.values()
+            .filter(|(_, outside)| *outside > 0)
+            .map(|(node, _)| node.clone())
+            .collect();
+
+        while let Some(node) = work.pop() {
+            if alive.insert(node.address()) {
+                node.edges(&mut |child| work.push(child));
+            }
+        }
+
+        drop(nodes);
+        let garbage: Vec<Rc<Vars>> = roots.into_iter()
+            .filter(|frame| !alive.contains(&(Rc::as_ptr(frame) as usize)))
+            .collect();
+
+        for frame in &garbage {
+            // Dropped after the borrow ends, since freeing them can reach other frames
+            let vars = std::mem::take(&mut *frame.borrow_mut());
+            drop(vars);
+        }
+        drop(garbage);
+
+        let mut frames = self.frames.borrow_mut();
+        frames.retain(|_, frame| frame.strong_count() > 0);
+        self.limit.set(frames.len() * 2);
+    }
+}
+
+// What can hold a frame, directly or through what it holds
+#[derive(Clone)]
+enum Node {
+    Frame(Rc<Vars>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Map>>),
+    Tuple(Rc<[Value]>),
+    Record(Rc<Record>),
+    Function(Rc<Function>),
+    Pointer(Rc<Pointer>)
+}
+
+impl Node {
+    fn of(value: &Value) -> Option<Node> {
+        Some(match value {
+            Value::List(list) => Node::List(Rc::clone(list)),
+            Value::Map(map) => Node::Map(Rc::clone(map)),
+            Value::Tuple(items) => Node::Tuple(Rc::clone(items)),
+            Value::Record(record) => Node::Record(Rc::clone(record)),
+            Value::Function(function) => Node::Function(Rc::clone(function)),
+            Value::Pointer(pointer) => Node::Pointer(Rc::clone(pointer)),
+            _ => return None
+        })
+    }
+
+    fn address(&self) -> usize {
+        match self {
+            Node::Frame(rc) => Rc::as_ptr(rc) as usize,
+            Node::List(rc) => Rc::as_ptr(rc) as usize,
+            Node::Map(rc) => Rc::as_ptr(rc) as usize,
+            Node::Tuple(rc) => Rc::as_ptr(rc) as *const () as usize,
+            Node::Record(rc) => Rc::as_ptr(rc) as usize,
+            Node::Function(rc) => Rc::as_ptr(rc) as usize,
+            Node::Pointer(rc) => Rc::as_ptr(rc) as usize
+        }
+    }
+
+    fn strong(&self) -> usize {
+        match self {
+            Node::Frame(rc) => Rc::strong_count(rc),
+            Node::List(rc) => Rc::strong_count(rc),
+            Node::Map(rc) => Rc::strong_count(rc),
+            Node::Tuple(rc) => Rc::strong_count(rc),
+            Node::Record(rc) => Rc::strong_count(rc),
+            Node::Function(rc) => Rc::strong_count(rc),
+            Node::Pointer(rc) => Rc::strong_count(rc)
+        }
+    }
+
+    // Gives visit each node this one holds, false when it is borrowed and cannot be looked in
+    fn edges(&self, visit: &mut impl FnMut(Node)) -> bool {
+        let values = |values: &mut dyn Iterator<Item = &Value>, visit: &mut dyn FnMut(Node)| {
+            values.filter_map(Node::of).for_each(visit);
+        };
+
+        match self {
+            Node::Frame(vars) => match vars.try_borrow() {
+                Ok(vars) => values(&mut vars.values(), visit),
+                Err(_) => return false
+            },
+            Node::List(items) => match items.try_borrow() {
+                Ok(items) => values(&mut items.iter(), visit),
+                Err(_) => return false
+            },
+            Node::Map(map) => match map.try_borrow() {
+                Ok(map) => values(&mut map.iter().map(|(_, value)| value), visit),
+                Err(_) => return false
+            },
+            Node::Tuple(items) => values(&mut items.iter(), visit),
+            Node::Record(record) => match record.values().try_borrow() {
+                Ok(fields) => values(&mut fields.iter(), visit),
+                Err(_) => return false
+            },
+            Node::Function(function) => {
+                for frame in function.scope().frames() {
+                    visit(Node::Frame(Rc::clone(&frame.0)));
+                }
+            }
+            Node::Pointer(pointer) => visit(match pointer.slot() {
+                Slot::Var(frame, _) | Slot::Global(frame, _) => Node::Frame(Rc::clone(&frame.0)),
+                Slot::Item(list, _) => Node::List(Rc::clone(list)),
+                Slot::Part(items, _) => Node::Tuple(Rc::clone(items)),
+                Slot::Entry(map, _) => Node::Map(Rc::clone(map)),
+                Slot::Field(record, _) => Node::Record(Rc::clone(record))
+            })
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Program;
+    use crate::parser::{parse, Parser};
+
+    fn load(source: &str) -> Program {
+        let mut program = Program::new();
+        for def in parse(source, "test").unwrap() {
+            program.add(def);
+        }
+        program.load().unwrap();
+        program
+    }
+
+    fn eval(program: &mut Program, source: &str) -> String {
+        let expr = Parser::new(source, "test").unwrap().parse_expr().unwrap();
+        program.eval(&expr).unwrap().string()
+    }
+
+    const SOURCE: &str = "
+        DEFINE work := FUNCTION[n] {
+            helper := FUNCTION[x] { RETURN x + n }
+            xs := [1, 2, 3]
+            p := &xs
+            RETURN helper(1)
+        }
+
+        DEFINE counter := FUNCTION[] {
+            n := 0
+            inc := FUNCTION[] {
+                n = n + 1
+                RETURN n
+            }
+            RETURN inc
+        }
+
+        DEFINE repeat := FUNCTION[times] {
+            total := 0
+            FOR i := 1 TO times {
+                total = total + work(i)
+            }
+            RETURN total
+        }
+    ";
+
+    #[test]
+    fn frames_holding_their_own_closures_are_freed() {
+        let mut program = load(SOURCE);
+        assert_eq!(eval(&mut program, "repeat(20000)"), "200030000");
+
+        program.bindings().collector().collect();
+        // Only the globals are left
+        assert_eq!(program.bindings().collector().len(), 1);
+    }
+
+    #[test]
+    fn closures_still_held_keep_their_frames() {
+        let mut program = load(&format!("{SOURCE}
+            DEFINE c := counter()
+            DEFINE n := c() + c()
+        "));
+        eval(&mut program, "repeat(5000)");
+        program.bindings().collector().collect();
+
+        assert_eq!(eval(&mut program, "c()"), "3");
+    }
+}
@@ -1,96 +1,331 @@
-use crate::expressions::{Function, Builtin, CallExpr};
+use crate::expressions::{Builtin, call};
+use crate::builtins::BUILTINS;
 use crate::statements::AddVarStmt;
+use crate::records::RecordType;
+use crate::unions::Union;
+use crate::value::{Value, Slot};
+use crate::collector::Collector;
+use crate::error::{ErrorKind, Result, TraceEntry};
+use crate::span::Span;
 use std::collections::HashMap;
+use std::cell::RefCell;
 use std::rc::Rc;
 use std::fmt::Write;
-use std::any::Any;
 
-pub trait Expr: Any {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr>;
+pub trait Expr: 'static {
+    fn value(&self, bindings: &mut Bindings) -> Result<Value>;
     fn string(&self) -> String;
+    fn span(&self) -> &Span;
+
+    // What RETURN does with the expression. Calls, and the branches that end in them, give
+    // back Flow::Tail so the returning function's call makes them in a loop instead of nesting
+    fn tail(&self, bindings: &mut Bindings) -> Result<Flow> {
+        Ok(Flow::Return(self.value(bindings)?))
+    }
+}
+
+// A call whose arguments are evaluated but which has not been made yet
+#[derive(Clone)]
+pub struct TailCall {
+    pub callee: Value,
+    pub args: Vec<Value>,
+    pub named: Vec<(String, Value)>,
+    pub site: Span
+}
+
+// How control leaves a statement, anything but Next unwinds to the enclosing loop or function
+#[derive(Clone)]
+pub enum Flow {
+    Next,
+    Break(Option<String>),
+    Continue(Option<String>),
+    Return(Value),
+    Tail(Box<TailCall>)
 }
 
 pub trait Stmt {
-    fn execute(&self, bindings: &mut Bindings);
+    fn execute(&self, bindings: &mut Bindings) -> Result<Flow>;
     fn string(&self) -> String;
+    fn span(&self) -> &Span;
 }
 
 pub trait Cell: Expr {
-    fn change(&self, bindings: &mut Bindings, expr: Rc<dyn Expr>);
+    fn change(&self, bindings: &mut Bindings, value: Value) -> Result<()>;
+
+    // Where the cell is right now, which is what a pointer to it keeps
+    fn slot(&self, bindings: &mut Bindings) -> Result<Slot>;
 }
 
-#[derive(Clone)]
-pub struct Frame(HashMap<String, Rc<dyn Expr>>);
+impl Expr for Rc<dyn Expr> {
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        (**self).value(bindings)
+    }
+
+    fn string(&self) -> String {
+        (**self).string()
+    }
+
+    fn span(&self) -> &Span {
+        (**self).span()
+    }
+
+    fn tail(&self, bindings: &mut Bindings) -> Result<Flow> {
+        (**self).tail(bindings)
+    }
+}
+
+impl Expr for Rc<dyn Cell> {
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        (**self).value(bindings)
+    }
+
+    fn string(&self) -> String {
+        (**self).string()
+    }
+
+    fn span(&self) -> &Span {
+        (**self).span()
+    }
+
+    fn tail(&self, bindings: &mut Bindings) -> Result<Flow> {
+        (**self).tail(bindings)
+    }
+}
+
+impl Cell for Rc<dyn Cell> {
+    fn change(&self, bindings: &mut Bindings, value: Value) -> Result<()> {
+        (**self).change(bindings, value)
+    }
+
+    fn slot(&self, bindings: &mut Bindings) -> Result<Slot> {
+        (**self).slot(bindings)
+    }
+}
+
+// Shared between every Bindings that holds it, which is how functions capture the frames
+// they were created in and still see later changes to them
+#[derive(Clone, Default)]
+pub struct Frame(pub(crate) Rc<RefCell<HashMap<String, Value>>>);
 
 impl Frame {
     pub fn new() -> Frame {
-        Frame(HashMap::new())
+        Frame::default()
+    }
+
+    pub fn entries(&self) -> Vec<(String, Value)> {
+        self.0.borrow().iter().map(|(name, value)| (name.clone(), value.clone())).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.0.borrow().get(name).cloned()
+    }
+
+    // Only for names the frame already holds
+    pub fn set(&self, name: &str, value: Value) {
+        *self.0.borrow_mut().get_mut(name).unwrap() = value;
+    }
+
+    pub fn same(&self, other: &Frame) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
     }
 }
 
+// How many calls may be in progress at once unless a program sets its own, tail calls do
+// not count. Each call takes several Rust frames, and this many fit in the 8 MB stack of a
+// main thread even in a debug build
+pub const RECURSION_LIMIT: usize = 200;
+
+// Shared by every Bindings created for a call so errors can see how they were reached
 #[derive(Clone)]
-pub struct Bindings(Vec<Frame>);
+pub struct CallStack {
+    entries: Rc<RefCell<Vec<TraceEntry>>>,
+    limit: usize
+}
+
+impl Default for CallStack {
+    fn default() -> CallStack {
+        CallStack { entries: Rc::default(), limit: RECURSION_LIMIT }
+    }
+}
+
+impl CallStack {
+    // Stop before the Rust stack runs out, the limit must suit the thread running the program
+    pub fn push(&self, entry: TraceEntry) -> Result<()> {
+        let mut entries = self.entries.borrow_mut();
+        if entries.len() >= self.limit {
+            return Err(ErrorKind::RecursionLimit(self.limit).into());
+        }
+
+        entries.push(entry);
+        Ok(())
+    }
+
+    pub fn pop(&self) {
+        self.entries.borrow_mut().pop();
+    }
+
+    // For a tail call, which takes the place of the function that made it
+    pub fn replace(&self, entry: TraceEntry) {
+        *self.entries.borrow_mut().last_mut().unwrap() = entry;
+    }
+
+    pub fn trace(&self) -> Vec<TraceEntry> {
+        self.entries.borrow().clone()
+    }
+}
+
+// What int arithmetic does when the result does not fit in an i128
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Overflow {
+    #[default]
+    Promote,
+    Checked,
+    Wrapping,
+    Saturating
+}
+
+#[derive(Clone, Default)]
+pub struct Bindings {
+    frames: Vec<Frame>,
+    stack: CallStack,
+    overflow: Overflow,
+    collector: Rc<Collector>
+}
 
 impl Bindings {
     pub fn new() -> Bindings {
-        Bindings(Vec::new())
+        Bindings::default()
+    }
+
+    // The frames of scope, with the call stack, overflow mode and collector of self
+    pub fn new_within(&self, scope: &Bindings) -> Bindings {
+        Bindings {
+            frames: scope.frames.clone(),
+            stack: self.stack.clone(),
+            overflow: self.overflow,
+            collector: Rc::clone(&self.collector)
+        }
+    }
+
+    pub fn stack(&self) -> &CallStack {
+        &self.stack
+    }
+
+    pub fn collector(&self) -> &Collector {
+        &self.collector
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    // For a function made here, which holds on to every frame and may be stored in one
+    pub fn capture(&self) {
+        for frame in &self.frames {
+            self.collector.track(frame);
+        }
     }
 
-    pub fn new_with_globals(&self) -> Bindings {
-        Bindings(vec![self.0[0].clone()])
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.stack.limit = limit;
+    }
+
+    pub fn globals(&self) -> &Frame {
+        &self.frames[0]
     }
 
     pub fn new_frame(&mut self) {
-        self.0.push(Frame::new());
+        self.frames.push(Frame::new());
+    }
+
+    pub fn pop_frame(&mut self) {
+        self.frames.pop();
     }
 
-    pub fn add(&mut self, name: String, expr: Rc<dyn Expr>) {
-        let last_frame = self.0.last_mut().unwrap();
+    pub fn add(&mut self, name: String, value: Value) -> Result<()> {
+        let mut last_frame = self.frames.last().unwrap().0.borrow_mut();
 
-        if last_frame.0.contains_key(&name) {
-            panic!("VARIABLE {} ALREADY PRESENT", name);
+        if last_frame.contains_key(&name) {
+            return Err(ErrorKind::Redefinition(name).into());
         }
 
-        last_frame.0.insert(name, expr);
+        last_frame.insert(name, value);
+        Ok(())
     }
 
-    pub fn change(&mut self, name: &str, expr: Rc<dyn Expr>) {
-        for frame in self.0.iter_mut().skip(1).rev() {
-            if !frame.0.contains_key(name) { continue; }
+    pub fn change(&mut self, name: &str, value: Value) -> Result<()> {
+        for frame in self.frames.iter().skip(1).rev() {
+            let mut frame = frame.0.borrow_mut();
+            if !frame.contains_key(name) { continue; }
 
-            *frame.0.get_mut(name).unwrap() = expr;
-            return;
+            *frame.get_mut(name).unwrap() = value;
+            return Ok(());
         }
 
-        panic!("VARIABLE {} NOT FOUND", name);
+        Err(ErrorKind::UnboundVariable(name.to_string()).into())
     }
     
-    pub fn get(&self, name: &str) -> Rc<dyn Expr> {
-        for frame in self.0.iter().rev() {
-            if !frame.0.contains_key(name) { continue; }
+    // The frame holding name, globals cannot be changed through it just as with change
+    pub fn slot(&self, name: &str) -> Result<Slot> {
+        for (i, frame) in self.frames.iter().enumerate().rev() {
+            if !frame.0.borrow().contains_key(name) { continue; }
+
+            // A pointer to the slot may be stored in the frame it points into
+            self.collector.track(frame);
+            let slot = if i == 0 { Slot::Global } else { Slot::Var };
+            return Ok(slot(frame.clone(), name.to_string()));
+        }
 
-            return Rc::clone(&frame.0[name]);
+        Err(ErrorKind::UnboundVariable(name.to_string()).into())
+    }
+
+    pub fn get(&self, name: &str) -> Result<Value> {
+        for frame in self.frames.iter().rev() {
+            let frame = frame.0.borrow();
+            if !frame.contains_key(name) { continue; }
+
+            return Ok(frame[name].clone());
         }
 
-        panic!("VARIABLE {} NOT FOUND", name);
+        Err(ErrorKind::UnboundVariable(name.to_string()).into())
     }
 }
 
 #[derive(Clone)] //TMP1
-pub struct Block(Vec<Rc<dyn Stmt>>);
+pub struct Block(Vec<Rc<dyn Stmt>>, Span);
 
 impl Block {
-    pub fn new(statements: Vec<Rc<dyn Stmt>>) -> Block {
-        Block(statements)
+    pub fn new(statements: Vec<Rc<dyn Stmt>>, span: Span) -> Block {
+        Block(statements, span)
+    }
+
+    fn run(&self, bindings: &mut Bindings) -> Result<Flow> {
+        for stmt in &self.0 {
+            let flow = stmt.execute(bindings)?;
+            if !matches!(flow, Flow::Next) {
+                return Ok(flow);
+            }
+        }
+
+        Ok(Flow::Next)
     }
 }
 
 impl Stmt for Block {
-    fn execute(&self, bindings: &mut Bindings) {
+    fn execute(&self, bindings: &mut Bindings) -> Result<Flow> {
         bindings.new_frame();
-        for stmt in &self.0 {
-            stmt.execute(bindings);
-        }
+        let res = self.run(bindings);
+        bindings.pop_frame();
+
+        res
     }
 
     fn string(&self) -> String {
@@ -98,35 +333,94 @@ impl Stmt for Block {
 
         for stmt in &self.0 {
             for line in stmt.string().lines() {
-                write!(&mut res, "    {line}").unwrap();
+                writeln!(&mut res, "    {line}").unwrap();
             }
         }
 
         format!("{{\n{}}}", res)
     }
+
+    fn span(&self) -> &Span {
+        &self.1
+    }
 }
 
-pub struct Definition(Rc<dyn Stmt>); //FIXME: add a generic
+// What can stand at the top level of a program
+pub enum Definition {
+    Var(Rc<dyn Stmt>), //FIXME: add a generic
+    Record(Rc<RecordType>),
+    Union(Rc<Union>, Vec<Rc<RecordType>>)
+}
 
 impl Definition {
     pub fn new<E: Expr>(statement: AddVarStmt<E>) -> Definition {
-        Definition(Rc::new(statement))
+        Definition::Var(Rc::new(statement))
+    }
+
+    pub fn record(kind: RecordType) -> Definition {
+        Definition::Record(Rc::new(kind))
+    }
+
+    // Every variant is named by the union and has the fields given for it
+    pub fn union(union: Union, fields: Vec<Vec<String>>) -> Definition {
+        let union = Rc::new(union);
+        let variants = union.variants().iter().zip(fields)
+            .map(|(name, fields)| Rc::new(RecordType::variant(name.clone(), fields, Rc::clone(&union), union.span().clone())))
+            .collect();
+
+        Definition::Union(union, variants)
     }
 }
 
 impl Stmt for Definition {
-    fn execute(&self, bindings: &mut Bindings) {
-        self.0.execute(bindings)
+    fn execute(&self, bindings: &mut Bindings) -> Result<Flow> {
+        match self {
+            Definition::Var(statement) => statement.execute(bindings),
+            Definition::Record(kind) => {
+                bindings.add(kind.name().to_string(), Value::RecordType(Rc::clone(kind)))
+                    .map_err(|err| err.at(kind.span()))?;
+                Ok(Flow::Next)
+            }
+            Definition::Union(_, variants) => {
+                for kind in variants {
+                    bindings.add(kind.name().to_string(), Value::RecordType(Rc::clone(kind)))
+                        .map_err(|err| err.at(kind.span()))?;
+                }
+                Ok(Flow::Next)
+            }
+        }
     }
 
     fn string(&self) -> String {
-        format!("DEFINE {}", self.0.string())
+        match self {
+            Definition::Var(statement) => format!("DEFINE {}", statement.string()),
+            Definition::Record(kind) => kind.string(),
+            Definition::Union(union, variants) => {
+                let variants: Vec<String> = variants.iter().map(|kind| kind.signature()).collect();
+                format!("UNION {}[{}]", union.name(), variants.join(", "))
+            }
+        }
+    }
+
+    fn span(&self) -> &Span {
+        match self {
+            Definition::Var(statement) => statement.span(),
+            Definition::Record(kind) => kind.span(),
+            Definition::Union(union, _) => union.span()
+        }
     }
 }
 
 pub struct Program {
     bindings: Bindings,
-    prog: Vec<Definition>
+    prog: Vec<Definition>,
+    loaded: usize
+}
+
+impl Default for Program {
+    fn default() -> Program {
+        Program::new()
+    }
 }
 
 impl Program {
@@ -134,29 +428,133 @@ impl Program {
         let mut bindings = Bindings::new();
         bindings.new_frame();
 
-        bindings.add(
-            "print".to_string(),
-            Rc::new(Builtin::new(
-                "print".to_string(),
-                |x| println!("{}", x)
-            ))
-        );
+        for (name, arity, body) in BUILTINS {
+            bindings.add(
+                name.to_string(),
+                Value::Builtin(Rc::new(Builtin::new(name.to_string(), *arity, *body, Span::caller())))
+            ).unwrap();
+        }
 
-        Program { bindings, prog: Vec::new() }
+        Program { bindings, prog: Vec::new(), loaded: 0 }
     }
 
     pub fn add(&mut self, def: Definition) {
         self.prog.push(def);
     }
 
-    pub fn run(&mut self) {
-        for stmt in &self.prog {
-            stmt.execute(&mut self.bindings);
+    pub fn bindings(&self) -> &Bindings {
+        &self.bindings
+    }
+
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.bindings.set_overflow(overflow);
+    }
+
+    // RECURSION_LIMIT suits a main thread, a program run on a larger stack can allow more
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.bindings.set_recursion_limit(limit);
+    }
+
+    pub fn load(&mut self) -> Result<()> {
+        while self.loaded < self.prog.len() {
+            // Count the definition before executing it so a failing one is not retried
+            self.loaded += 1;
+            self.prog[self.loaded - 1].execute(&mut self.bindings)?;
         }
 
-        let main = self.bindings.get("main");
-        let main = (main as Rc<dyn Any>).downcast::<Function>().unwrap();
+        Ok(())
+    }
+
+    pub fn eval<E: Expr>(&mut self, expr: &E) -> Result<Value> {
+        expr.value(&mut self.bindings)
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        self.load()?;
+
+        let main = self.bindings.get("main")?;
+        call(main, Vec::new(), Vec::new(), &self.bindings, None)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    // Each level nests a few blocks and operators, which is where the Rust stack goes
+    const SOURCE: &str = "
+        DEFINE down := FUNCTION[n] {
+            IF n >= 0 {
+                WHILE TRUE {
+                    FOR i := 0 TO 1 {
+                        RETURN [1 + down(n + 1) * 2][0]
+                    }
+                }
+            }
+        }
+
+        DEFINE main := FUNCTION[] {
+            print(down(0))
+        }
+    ";
+
+    // The kind of error running SOURCE with limit ends in, on a thread with stack bytes
+    fn run_deep(limit: Option<usize>, stack: usize) -> String {
+        let thread = std::thread::Builder::new().stack_size(stack).spawn(move || {
+            let mut program = Program::new();
+            if let Some(limit) = limit {
+                program.set_recursion_limit(limit);
+            }
+            for def in parse(SOURCE, "test").unwrap() {
+                program.add(def);
+            }
+            program.run().unwrap_err().kind.to_string()
+        });
+
+        thread.unwrap().join().unwrap()
+    }
+
+    // Far more calls than the default limit allows, each a tail call made from the branches
+    // of an IF and a MATCH, so none of them nests
+    const TAIL_CALLS: &str = "
+        UNION Parity[Even, Odd]
+
+        DEFINE parities := [NEW Even {}, NEW Odd {}]
+
+        DEFINE down := FUNCTION[n] {
+            RETURN IF n == 0 THEN n ELSE MATCH parities[n % 2] {
+                Even THEN down(n - 1),
+                Odd THEN down(n - 1)
+            }
+        }
+    ";
+
+    #[test]
+    fn tail_calls_run_in_constant_stack() {
+        let thread = std::thread::Builder::new().stack_size(8 * 1024 * 1024).spawn(|| {
+            let mut program = Program::new();
+            for def in parse(TAIL_CALLS, "test").unwrap() {
+                program.add(def);
+            }
+            program.load().unwrap();
+
+            let down = program.bindings().get("down").unwrap();
+            call(down, vec![Value::Int(1_000_000)], Vec::new(), program.bindings(), None).unwrap().string()
+        });
+
+        assert_eq!(thread.unwrap().join().unwrap(), "0");
+    }
+
+    #[test]
+    fn default_recursion_limit_fits_a_main_thread() {
+        assert_eq!(run_deep(None, 8 * 1024 * 1024), "RECURSION LIMIT OF 200 CALLS REACHED");
+    }
 
-        CallExpr::new((*main).clone(), Vec::new()).value(&mut self.bindings);
+    #[test]
+    fn recursion_limit_can_be_raised_for_a_larger_stack() {
+        assert_eq!(run_deep(Some(2000), 256 * 1024 * 1024), "RECURSION LIMIT OF 2000 CALLS REACHED");
     }
 }
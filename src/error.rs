@@ -0,0 +1,270 @@
+use crate::span::{Span, Sources};
+use std::fmt::{self, Write};
+use std::rc::Rc;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorKind {
+    UnboundVariable(String),
+    Redefinition(String),
+    TypeMismatch { expected: &'static str, found: &'static str },
+    Incomparable { left: &'static str, right: &'static str },
+    IndexOutOfRange { index: i128, len: usize },
+    InvalidRange { start: i128, end: i128 },
+    InvalidConversion { text: String, to: &'static str },
+    NotCallable(&'static str),
+    // A max of usize::MAX means any number from min on
+    ArityMismatch { name: Rc<str>, min: usize, max: usize, found: usize },
+    NonBoolCondition(&'static str),
+    OutsideLoop { keyword: &'static str, label: Option<String> },
+    ZeroStep,
+    Overflow(&'static str),
+    DivisionByZero,
+    IntTooLarge(String),
+    EmptyList,
+    InvalidKey(&'static str),
+    MissingKey(String),
+    UnknownField { record: Rc<str>, field: String },
+    MissingField { record: Rc<str>, field: String },
+    Immutable(&'static str),
+    UnpackMismatch { expected: usize, found: usize },
+    NonExhaustive { union: Rc<str>, variant: String },
+    NoMatch(String),
+    UnknownArgument { function: Rc<str>, arg: String },
+    DuplicateArgument { function: Rc<str>, arg: String },
+    MissingArgument { function: Rc<str>, arg: String },
+    RecursionLimit(usize)
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UnboundVariable(name) => write!(f, "VARIABLE {name} NOT FOUND"),
+            ErrorKind::Redefinition(name) => write!(f, "VARIABLE {name} ALREADY PRESENT"),
+            ErrorKind::TypeMismatch { expected, found } => write!(f, "EXPECTED {expected}, FOUND {found}"),
+            ErrorKind::Incomparable { left, right } => write!(f, "CANNOT ORDER {left} AGAINST {right}"),
+            ErrorKind::IndexOutOfRange { index, len } => write!(f, "INDEX {index} OUT OF RANGE FOR LENGTH {len}"),
+            ErrorKind::InvalidRange { start, end } => write!(f, "RANGE {start} TO {end} ENDS BEFORE IT STARTS"),
+            ErrorKind::InvalidConversion { text, to } => write!(f, "CANNOT CONVERT {text} TO {to}"),
+            ErrorKind::NotCallable(found) => write!(f, "{found} IS NOT A FUNCTION"),
+            ErrorKind::ArityMismatch { name, min, max, found } if min == max => {
+                write!(f, "{name} TAKES {min} ARGUMENTS, GIVEN {found}")
+            }
+            ErrorKind::ArityMismatch { name, min, max: usize::MAX, found } => {
+                write!(f, "{name} TAKES AT LEAST {min} ARGUMENTS, GIVEN {found}")
+            }
+            ErrorKind::ArityMismatch { name, min, max, found } => {
+                write!(f, "{name} TAKES {min} TO {max} ARGUMENTS, GIVEN {found}")
+            }
+            ErrorKind::NonBoolCondition(found) => write!(f, "CONDITION MUST BE BOOL, FOUND {found}"),
+            ErrorKind::OutsideLoop { keyword, label: Some(label) } => write!(f, "{keyword} {label} OUTSIDE OF A LOOP LABELLED {label}"),
+            ErrorKind::OutsideLoop { keyword, label: None } => write!(f, "{keyword} OUTSIDE OF A LOOP"),
+            ErrorKind::ZeroStep => write!(f, "FOR STEP MUST NOT BE ZERO"),
+            ErrorKind::Overflow(op) => write!(f, "INTEGER OVERFLOW IN '{op}'"),
+            ErrorKind::DivisionByZero => write!(f, "DIVISION BY ZERO"),
+            ErrorKind::IntTooLarge(n) => write!(f, "INT {n} IS TOO LARGE HERE"),
+            ErrorKind::EmptyList => write!(f, "LIST IS EMPTY"),
+            ErrorKind::InvalidKey(found) => write!(f, "{found} CANNOT BE A MAP KEY"),
+            ErrorKind::MissingKey(key) => write!(f, "KEY {key} NOT FOUND"),
+            ErrorKind::UnknownField { record, field } => write!(f, "RECORD {record} HAS NO FIELD {field}"),
+            ErrorKind::MissingField { record, field } => write!(f, "FIELD {field} OF {record} NOT GIVEN"),
+            ErrorKind::Immutable(found) => write!(f, "{found} CANNOT BE CHANGED"),
+            ErrorKind::UnpackMismatch { expected, found } => write!(f, "CANNOT UNPACK {found} VALUES INTO {expected} NAMES"),
+            ErrorKind::NonExhaustive { union, variant } => write!(f, "MATCH ON {union} MISSES {variant}"),
+            ErrorKind::NoMatch(name) => write!(f, "NO MATCH ARM FOR {name}"),
+            ErrorKind::UnknownArgument { function, arg } => write!(f, "{function} HAS NO ARGUMENT {arg}"),
+            ErrorKind::DuplicateArgument { function, arg } => write!(f, "ARGUMENT {arg} OF {function} GIVEN TWICE"),
+            ErrorKind::MissingArgument { function, arg } => write!(f, "ARGUMENT {arg} OF {function} NOT GIVEN"),
+            ErrorKind::RecursionLimit(limit) => write!(f, "RECURSION LIMIT OF {limit} CALLS REACHED")
+        }
+    }
+}
+
+// How many calls of a long traceback are shown at its start and at its end
+const TRACE_ENDS: usize = 10;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceEntry {
+    pub function: String,
+    pub site: Option<Span>
+}
+
+impl TraceEntry {
+    pub fn new(function: String, site: Option<Span>) -> TraceEntry {
+        TraceEntry { function, site }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub span: Option<Span>,
+    pub trace: Vec<TraceEntry>
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn new(kind: ErrorKind, span: &Span) -> Error {
+        Error { kind, span: Some(span.clone()), trace: Vec::new() }
+    }
+
+    // The innermost node that knows where it is wins, so only fill in a missing span
+    pub fn at(mut self, span: &Span) -> Error {
+        if self.span.is_none() {
+            self.span = Some(span.clone());
+        }
+
+        self
+    }
+
+    pub fn render(&self, sources: &Sources) -> String {
+        let mut res = sources.render("RUNTIME ERROR", &self.kind.to_string(), self.span.as_ref());
+
+        if self.trace.is_empty() {
+            return res;
+        }
+
+        write!(&mut res, "\nTRACEBACK (MOST RECENT CALL LAST):").unwrap();
+        for (i, entry) in self.trace.iter().enumerate() {
+            // Deep recursion would bury the error, so only the calls at either end are shown
+            let skipped = self.trace.len().saturating_sub(2 * TRACE_ENDS);
+            if skipped > 0 && (TRACE_ENDS..TRACE_ENDS + skipped).contains(&i) {
+                if i == TRACE_ENDS {
+                    write!(&mut res, "\n  ... {skipped} MORE CALLS").unwrap();
+                }
+                continue;
+            }
+
+            match &entry.site {
+                Some(site) => write!(&mut res, "\n  {} CALLED AT {site}", entry.function).unwrap(),
+                None => write!(&mut res, "\n  {}", entry.function).unwrap()
+            }
+
+            if let Some(line) = entry.site.as_ref().and_then(|site| sources.line(site)) {
+                write!(&mut res, "\n      {}", line.trim()).unwrap();
+            }
+        }
+
+        res
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error { kind, span: None, trace: Vec::new() }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.span {
+            Some(span) => write!(f, "{span}: {}", self.kind),
+            None => write!(f, "{}", self.kind)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Program;
+    use crate::parser::parse;
+
+    // The error running main in source ends in
+    fn run(source: &str) -> Error {
+        let mut program = Program::new();
+        for def in parse(source, "test").unwrap() {
+            program.add(def);
+        }
+        program.run().unwrap_err()
+    }
+
+    fn kind(body: &str) -> (ErrorKind, usize, usize) {
+        let err = run(&format!("DEFINE main := FUNCTION[] {{\n{body}\n}}"));
+        let span = err.span.unwrap();
+        (err.kind, span.line, span.col)
+    }
+
+    #[test]
+    fn failures_are_errors_with_the_span_of_the_node() {
+        assert_eq!(kind("print(nope)"), (ErrorKind::UnboundVariable("nope".to_string()), 2, 7));
+        assert_eq!(kind("x := 1\nx := 2"), (ErrorKind::Redefinition("x".to_string()), 3, 1));
+        assert_eq!(kind("x := 1 + TRUE"), (ErrorKind::TypeMismatch { expected: "NUMBER", found: "BOOL" }, 2, 10));
+        assert_eq!(kind("x := 1\nx(2)"), (ErrorKind::NotCallable("INT"), 3, 1));
+        assert_eq!(kind("x := [1][3]"), (ErrorKind::IndexOutOfRange { index: 3, len: 1 }, 2, 10));
+        assert_eq!(kind("IF 1 { }"), (ErrorKind::NonBoolCondition("INT"), 2, 4));
+    }
+
+    #[test]
+    fn at_only_fills_a_missing_span() {
+        let span = |line| Span::new("test".into(), line, 1, 1);
+
+        let err = Error::from(ErrorKind::DivisionByZero).at(&span(1)).at(&span(2));
+        assert_eq!(err.span, Some(span(1)));
+        assert_eq!(err.to_string(), "test:1:1: DIVISION BY ZERO");
+        assert_eq!(Error::from(ErrorKind::EmptyList).to_string(), "LIST IS EMPTY");
+    }
+
+    // The render of the error running main in source, loaded as the file test
+    fn render(source: &str) -> String {
+        let mut sources = Sources::new();
+        sources.add("test", source);
+        run(source).render(&sources)
+    }
+
+    #[test]
+    fn tracebacks_list_calls_outermost_first() {
+        let source = "\
+DEFINE inner := FUNCTION[x] {
+    RETURN x / 0
+}
+
+DEFINE outer := FUNCTION[] {
+    RETURN 1 + inner(1)
+}
+
+DEFINE main := FUNCTION[] {
+    outer()
+}";
+
+        assert_eq!(render(source), "\
+RUNTIME ERROR: DIVISION BY ZERO
+ --> test:2:14
+  |
+2 |     RETURN x / 0
+  |              ^
+TRACEBACK (MOST RECENT CALL LAST):
+  main
+  outer CALLED AT test:10:5
+      outer()
+  inner CALLED AT test:6:16
+      RETURN 1 + inner(1)");
+    }
+
+    #[test]
+    fn long_tracebacks_keep_only_their_ends() {
+        let trace = (0..2 * TRACE_ENDS + 5).map(|i| TraceEntry::new(format!("f{i}"), None)).collect();
+        let err = Error { kind: ErrorKind::EmptyList, span: None, trace };
+
+        let mut expected = "LIST IS EMPTY\nTRACEBACK (MOST RECENT CALL LAST):".to_string();
+        for i in 0..TRACE_ENDS {
+            expected += &format!("\n  f{i}");
+        }
+        expected += "\n  ... 5 MORE CALLS";
+        for i in TRACE_ENDS + 5..2 * TRACE_ENDS + 5 {
+            expected += &format!("\n  f{i}");
+        }
+
+        assert_eq!(err.render(&Sources::new()), format!("RUNTIME ERROR: {expected}"));
+    }
+
+    #[test]
+    fn short_tracebacks_are_kept_whole() {
+        let trace: Vec<TraceEntry> = (0..2 * TRACE_ENDS).map(|i| TraceEntry::new(format!("f{i}"), None)).collect();
+        let err = Error { kind: ErrorKind::EmptyList, span: None, trace };
+        assert!(!err.render(&Sources::new()).contains("MORE CALLS"));
+        assert!(err.render(&Sources::new()).ends_with(&format!("f{}", 2 * TRACE_ENDS - 1)));
+    }
+}
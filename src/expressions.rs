@@ -1,265 +1,828 @@
-use crate::core::{Expr, Cell, Stmt, Bindings, Block};
+use crate::core::{Expr, Cell, Stmt, Bindings, Block, Flow, TailCall};
+use crate::value::{Value, Pointer, Slot, Map, Key};
+use crate::bigint::BigInt;
+use crate::operations::int;
+use crate::builtins::{position, VARIADIC};
+use crate::error::{Error, ErrorKind, Result, TraceEntry};
+use crate::span::Span;
+use std::cell::RefCell;
 use std::rc::Rc;
 use std::fmt::Write;
-use std::any::Any;
 
-pub struct TextExpr(pub String);
+pub struct TextExpr(pub String, Span);
 
 impl TextExpr {
-    pub fn new(s: String) -> TextExpr {
-        TextExpr(s)
+    pub fn new(s: String, span: Span) -> TextExpr {
+        TextExpr(s, span)
     }
 }
 
 impl Expr for TextExpr {
-    fn value(&self, _bindings: &mut Bindings) -> Rc<dyn Expr> {
-        Rc::new(TextExpr::new(self.0.clone()))
+    fn value(&self, _bindings: &mut Bindings) -> Result<Value> {
+        Ok(Value::Text(self.0.as_str().into()))
     }
 
     fn string(&self) -> String {
-        self.0.clone()
+        format!("{:?}", self.0)
+    }
+
+    fn span(&self) -> &Span {
+        &self.1
     }
 }
 
-pub struct IntExpr(pub i128);
+pub struct IntExpr(pub i128, Span);
 
 impl IntExpr {
-    pub fn new(n: i128) -> IntExpr {
-        IntExpr(n)
+    pub fn new(n: i128, span: Span) -> IntExpr {
+        IntExpr(n, span)
     }
 }
 
 impl Expr for IntExpr {
-    fn value(&self, _bindings: &mut Bindings) -> Rc<dyn Expr> {
-        Rc::new(IntExpr::new(self.0))
+    fn value(&self, _bindings: &mut Bindings) -> Result<Value> {
+        Ok(Value::Int(self.0))
+    }
+
+    fn string(&self) -> String {
+        format!("{}", self.0)
+    }
+
+    fn span(&self) -> &Span {
+        &self.1
+    }
+}
+
+// Literals too large for an IntExpr
+pub struct BigIntExpr(pub Rc<BigInt>, Span);
+
+impl BigIntExpr {
+    pub fn new(n: BigInt, span: Span) -> BigIntExpr {
+        BigIntExpr(Rc::new(n), span)
+    }
+}
+
+impl Expr for BigIntExpr {
+    fn value(&self, _bindings: &mut Bindings) -> Result<Value> {
+        Ok(Value::Big(Rc::clone(&self.0)))
     }
 
     fn string(&self) -> String {
         format!("{}", self.0)
     }
+
+    fn span(&self) -> &Span {
+        &self.1
+    }
+}
+
+pub struct FloatExpr(pub f64, Span);
+
+impl FloatExpr {
+    pub fn new(n: f64, span: Span) -> FloatExpr {
+        FloatExpr(n, span)
+    }
 }
 
-pub struct BoolExpr(pub bool);
+impl Expr for FloatExpr {
+    fn value(&self, _bindings: &mut Bindings) -> Result<Value> {
+        Ok(Value::Float(self.0))
+    }
+
+    fn string(&self) -> String {
+        Value::Float(self.0).string()
+    }
+
+    fn span(&self) -> &Span {
+        &self.1
+    }
+}
+
+pub struct BoolExpr(pub bool, Span);
 
 impl BoolExpr {
-    pub fn new(b: bool) -> BoolExpr {
-        BoolExpr(b)
+    pub fn new(b: bool, span: Span) -> BoolExpr {
+        BoolExpr(b, span)
     }
 }
 
 impl Expr for BoolExpr {
-    fn value(&self, _bindings: &mut Bindings) -> Rc<dyn Expr> {
-        Rc::new(BoolExpr::new(self.0))
+    fn value(&self, _bindings: &mut Bindings) -> Result<Value> {
+        Ok(Value::Bool(self.0))
     }
 
     fn string(&self) -> String {
         if self.0 { "TRUE" } else { "FALSE" }.to_string()
     }
+
+    fn span(&self) -> &Span {
+        &self.1
+    }
+}
+
+// What statements, builtins and functions without a result give back
+pub struct UnitExpr(Span);
+
+impl UnitExpr {
+    pub fn new(span: Span) -> UnitExpr {
+        UnitExpr(span)
+    }
+}
+
+impl Expr for UnitExpr {
+    fn value(&self, _bindings: &mut Bindings) -> Result<Value> {
+        Ok(Value::Unit)
+    }
+
+    fn string(&self) -> String {
+        "UNIT".to_string()
+    }
+
+    fn span(&self) -> &Span {
+        &self.0
+    }
 }
 
 #[derive(Clone)] //TMP0
-pub struct VarExpr(String);
+pub struct VarExpr(String, Span);
 
 impl VarExpr {
-    pub fn new(name: String) -> VarExpr {
-        VarExpr(name)
+    pub fn new(name: String, span: Span) -> VarExpr {
+        VarExpr(name, span)
     }
 }
 
 impl Expr for VarExpr {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        bindings.get(&self.0)
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        bindings.get(&self.0).map_err(|err| err.at(&self.1))
     }
 
     fn string(&self) -> String {
         self.0.clone()
     }
+
+    fn span(&self) -> &Span {
+        &self.1
+    }
 }
 
 impl Cell for VarExpr {
-    fn change(&self, bindings: &mut Bindings, expr: Rc<dyn Expr>) {
-        bindings.change(&self.0, expr);
+    fn change(&self, bindings: &mut Bindings, value: Value) -> Result<()> {
+        bindings.change(&self.0, value).map_err(|err| err.at(&self.1))
+    }
+
+    fn slot(&self, bindings: &mut Bindings) -> Result<Slot> {
+        bindings.slot(&self.0).map_err(|err| err.at(&self.1))
     }
 }
 
-#[derive(Clone)]
-pub struct Pointer {
-    bindings: Bindings,
-    cell: Rc<dyn Cell>
+// The cell is resolved when the pointer is made, so it keeps pointing at the same
+// variable, item or field after indexes change or the function that made it returns
+pub struct RefExpr<C: Cell> {
+    cell: C,
+    span: Span
 }
 
-impl Pointer {
-    pub fn new(bindings: Bindings, cell: Rc<dyn Cell>) -> Pointer {
-        Pointer { bindings, cell }
+impl<C: Cell> RefExpr<C> {
+    pub fn new(cell: C, span: Span) -> RefExpr<C> {
+        RefExpr { cell, span }
     }
 }
 
-impl Expr for Pointer {
-    fn value(&self, _bindings: &mut Bindings) -> Rc<dyn Expr> {
-        Rc::new(self.clone())
+impl<C: Cell> Expr for RefExpr<C> {
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let slot = self.cell.slot(bindings)?;
+        Ok(Value::Pointer(Rc::new(Pointer::new(slot, self.cell.string()))))
     }
 
     fn string(&self) -> String {
-        format!("<POINTER TO {}>", self.cell.string())
+        format!("&{}", self.cell.string())
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
     }
 }
 
-pub struct RefExpr<C: Cell + Clone> { //TMP0
-    cell: C
+// Prefix operators bind looser than calls, indexing and fields, so an operand of those that
+// shows as one is put back in its parentheses
+pub fn postfix_operand<E: Expr>(expr: &E) -> String {
+    let string = expr.string();
+    if string.starts_with(['*', '&']) { format!("({string})") } else { string }
 }
 
-impl<C: Cell + Clone> RefExpr<C> {
-    pub fn new(cell: C) -> RefExpr<C> {
-        RefExpr { cell }
+#[derive(Clone)] //TMP0
+pub struct DerefExpr<E: Expr>(E, Span);
+
+impl<E: Expr> DerefExpr<E> {
+    pub fn new(pointer: E, span: Span) -> DerefExpr<E> {
+        DerefExpr(pointer, span)
+    }
+
+    fn pointer(&self, bindings: &mut Bindings) -> Result<Rc<Pointer>> {
+        match self.0.value(bindings)? {
+            Value::Pointer(pointer) => Ok(pointer),
+            value => Err(Error::new(ErrorKind::TypeMismatch { expected: "POINTER", found: value.type_name() }, self.0.span()))
+        }
     }
 }
 
-impl<C: Cell + Clone> Expr for RefExpr<C> { //TMP0
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        Rc::new(Pointer::new(
-            bindings.clone(),
-            Rc::new(self.cell.clone()) //FIXME: change field to Rc<dyn> !0
-        ))
+impl<E: Expr> Expr for DerefExpr<E> {
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        self.pointer(bindings)?.slot().get().map_err(|err| err.at(&self.1))
     }
 
     fn string(&self) -> String {
-        format!("&{}", self.cell.string())
+        format!("*{}", self.0.string())
+    }
+
+    fn span(&self) -> &Span {
+        &self.1
     }
 }
 
-#[derive(Clone)] //TMP0
-pub struct DerefExpr(Pointer);
+impl<E: Expr> Cell for DerefExpr<E> {
+    fn change(&self, bindings: &mut Bindings, value: Value) -> Result<()> {
+        self.pointer(bindings)?.slot().set(value).map_err(|err| err.at(&self.1))
+    }
 
-impl DerefExpr {
-    pub fn new(pointer: Pointer) -> DerefExpr {
-        DerefExpr(pointer)
+    fn slot(&self, bindings: &mut Bindings) -> Result<Slot> {
+        Ok(self.pointer(bindings)?.slot().clone())
     }
 }
 
-impl Expr for DerefExpr {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        self.0.cell.value(bindings)
+pub struct ListExpr(Vec<Rc<dyn Expr>>, Span);
+
+impl ListExpr {
+    pub fn new(items: Vec<Rc<dyn Expr>>, span: Span) -> ListExpr {
+        ListExpr(items, span)
+    }
+}
+
+impl Expr for ListExpr {
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let mut items = Vec::new();
+        for item in &self.0 {
+            items.push(item.value(bindings)?);
+        }
+
+        Ok(Value::List(Rc::new(RefCell::new(items))))
     }
 
     fn string(&self) -> String {
-        format!("*{}", self.0.string())
+        let items: Vec<String> = self.0.iter().map(|item| item.string()).collect();
+        format!("[{}]", items.join(", "))
+    }
+
+    fn span(&self) -> &Span {
+        &self.1
+    }
+}
+
+#[derive(Clone)]
+pub struct IndexExpr<T: Expr, I: Expr> {
+    target: T,
+    index: I,
+    span: Span
+}
+
+impl<T: Expr, I: Expr> IndexExpr<T, I> {
+    pub fn new(target: T, index: I, span: Span) -> IndexExpr<T, I> {
+        IndexExpr { target, index, span }
+    }
+
+}
+
+impl<T: Expr, I: Expr> Expr for IndexExpr<T, I> {
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        self.slot(bindings)?.get().map_err(|err| err.at(self.index.span()))
+    }
+
+    fn string(&self) -> String {
+        format!("{}[{}]", postfix_operand(&self.target), self.index.string())
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
     }
 }
 
-impl Cell for DerefExpr {
-    fn change(&self, bindings: &mut Bindings, expr: Rc<dyn Expr>) {
-        self.0.cell.change(bindings, expr) //FIXME: use self.0.bindings
+// A list item has to exist already but a map entry may be new. Tuple items can be read
+// but not changed
+impl<T: Expr, I: Expr> Cell for IndexExpr<T, I> {
+    fn change(&self, bindings: &mut Bindings, value: Value) -> Result<()> {
+        self.slot(bindings)?.set(value).map_err(|err| err.at(&self.span))
+    }
+
+    fn slot(&self, bindings: &mut Bindings) -> Result<Slot> {
+        match self.target.value(bindings)? {
+            Value::List(list) => {
+                let index = int(&self.index, bindings)?;
+                let len = list.borrow().len();
+                let index = position(index, len, false).map_err(|err| err.at(self.index.span()))?;
+                Ok(Slot::Item(list, index))
+            }
+            Value::Tuple(items) => {
+                let index = int(&self.index, bindings)?;
+                let index = position(index, items.len(), false).map_err(|err| err.at(self.index.span()))?;
+                Ok(Slot::Part(items, index))
+            }
+            Value::Map(map) => {
+                let key = Key::new(&self.index.value(bindings)?).map_err(|err| err.at(self.index.span()))?;
+                Ok(Slot::Entry(map, key))
+            }
+            value => {
+                Err(Error::new(ErrorKind::TypeMismatch { expected: "LIST, TUPLE OR MAP", found: value.type_name() }, self.target.span()))
+            }
+        }
+    }
+}
+
+// Always at least two items, or one written with a trailing comma
+pub struct TupleExpr(Vec<Rc<dyn Expr>>, Span);
+
+impl TupleExpr {
+    pub fn new(items: Vec<Rc<dyn Expr>>, span: Span) -> TupleExpr {
+        TupleExpr(items, span)
+    }
+}
+
+impl Expr for TupleExpr {
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let mut items = Vec::new();
+        for item in &self.0 {
+            items.push(item.value(bindings)?);
+        }
+
+        Ok(Value::Tuple(items.into()))
+    }
+
+    fn string(&self) -> String {
+        let items: Vec<String> = self.0.iter().map(|item| item.string()).collect();
+        if items.len() == 1 {
+            format!("({},)", items[0])
+        } else {
+            format!("({})", items.join(", "))
+        }
+    }
+
+    fn span(&self) -> &Span {
+        &self.1
     }
 }
 
+// A key and the value it maps to
+pub type Entry = (Rc<dyn Expr>, Rc<dyn Expr>);
+
+// Keys and values in the order they are written, a repeated key keeps its first place
+pub struct MapExpr(Vec<Entry>, Span);
+
+impl MapExpr {
+    pub fn new(entries: Vec<Entry>, span: Span) -> MapExpr {
+        MapExpr(entries, span)
+    }
+}
+
+impl Expr for MapExpr {
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let mut map = Map::new();
+        for (key, value) in &self.0 {
+            let key = Key::new(&key.value(bindings)?).map_err(|err| err.at(key.span()))?;
+            map.insert(key, value.value(bindings)?);
+        }
+
+        Ok(Value::Map(Rc::new(RefCell::new(map))))
+    }
+
+    fn string(&self) -> String {
+        let entries: Vec<String> = self.0.iter()
+            .map(|(key, value)| format!("{}: {}", key.string(), value.string()))
+            .collect();
+        format!("{{{}}}", entries.join(", "))
+    }
+
+    fn span(&self) -> &Span {
+        &self.1
+    }
+}
+
+// As a value it carries the frames it was evaluated in, so its body sees them when called
 #[derive(Clone)] //TMP1
 pub struct Function {
     name: String,
     args: Vec<String>,
-    body: Block
+    defaults: Vec<Rc<dyn Expr>>,
+    rest: Option<String>,
+    body: Block,
+    scope: Bindings,
+    span: Span
 }
 
 impl Function {
-    pub fn new(name: String, args: Vec<String>, body: Block) -> Function {
-        Function { name, args, body }
+    pub fn new(name: String, args: Vec<String>, body: Block, span: Span) -> Function {
+        Function { name, args, defaults: Vec::new(), rest: None, body, scope: Bindings::new(), span }
+    }
+
+    // Defaults belong to the last arguments, one each, and are evaluated on every call that
+    // leaves their argument out, after the arguments before them are bound
+    pub fn defaults(mut self, defaults: Vec<Rc<dyn Expr>>) -> Function {
+        self.defaults = defaults;
+        self
+    }
+
+    // Named after every other argument, it collects what is passed beyond them into a list
+    pub fn rest(mut self, rest: Option<String>) -> Function {
+        self.rest = rest;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn scope(&self) -> &Bindings {
+        &self.scope
+    }
+
+    // The same literal evaluated in the same frames, closures made by separate calls differ
+    pub fn same(&self, other: &Function) -> bool {
+        let (frames, others) = (self.scope.frames(), other.scope.frames());
+        self.name == other.name && self.span == other.span && frames.len() == others.len()
+            && frames.iter().zip(others).all(|(x, y)| x.same(y))
+    }
+
+    fn default(&self, i: usize) -> Option<&Rc<dyn Expr>> {
+        (i + self.defaults.len()).checked_sub(self.args.len()).map(|i| &self.defaults[i])
+    }
+
+    fn arity_mismatch(&self, found: usize) -> Error {
+        ErrorKind::ArityMismatch {
+            name: self.name.as_str().into(),
+            min: self.args.len() - self.defaults.len(),
+            max: if self.rest.is_some() { usize::MAX } else { self.args.len() },
+            found
+        }.into()
+    }
+
+    // Positional arguments fill the first slots, named ones the slots they name
+    fn bind(&self, mut args: Vec<Value>, named: Vec<(String, Value)>, bindings: &mut Bindings) -> Result<()> {
+        let extra = match &self.rest {
+            Some(_) if args.len() > self.args.len() => args.split_off(self.args.len()),
+            _ => Vec::new()
+        };
+
+        if args.len() > self.args.len() {
+            return Err(self.arity_mismatch(args.len()));
+        }
+
+        let found = args.len();
+        let mut values: Vec<Option<Value>> = args.into_iter().map(Some).collect();
+        values.resize(self.args.len(), None);
+
+        let positional_only = named.is_empty();
+        for (arg, value) in named {
+            let function = self.name.as_str().into();
+            let Some(i) = self.args.iter().position(|x| *x == arg) else {
+                return Err(ErrorKind::UnknownArgument { function, arg }.into());
+            };
+            if values[i].is_some() {
+                return Err(ErrorKind::DuplicateArgument { function, arg }.into());
+            }
+            values[i] = Some(value);
+        }
+
+        for (i, (arg, value)) in self.args.iter().zip(values).enumerate() {
+            let value = match (value, self.default(i)) {
+                (Some(value), _) => value,
+                (None, Some(default)) => default.value(bindings)?,
+                (None, None) if positional_only => return Err(self.arity_mismatch(found)),
+                (None, None) => {
+                    return Err(ErrorKind::MissingArgument { function: self.name.as_str().into(), arg: arg.clone() }.into());
+                }
+            };
+            bindings.add(arg.clone(), value)?;
+        }
+
+        if let Some(rest) = &self.rest {
+            bindings.add(rest.clone(), Value::List(Rc::new(RefCell::new(extra))))?;
+        }
+
+        Ok(())
     }
 }
 
 impl Expr for Function {
-    fn value(&self, _bindings: &mut Bindings) -> Rc<dyn Expr> {
-        Rc::new(self.clone()) // !1
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let mut function = self.clone(); // !1
+        bindings.capture();
+        function.scope = bindings.clone();
+        Ok(Value::Function(Rc::new(function)))
     }
 
     fn string(&self) -> String {
         let mut res = "FUNCTION[".to_string();
 
         for (i, arg) in self.args.iter().enumerate() {
-            if i == 0 {
-                write!(&mut res, "{arg}").unwrap();
-            } else {
-                write!(&mut res, ", {arg}").unwrap();
+            if i > 0 {
+                write!(&mut res, ", ").unwrap();
             }
+            match self.default(i) {
+                Some(default) => write!(&mut res, "{arg} := {}", default.string()).unwrap(),
+                None => write!(&mut res, "{arg}").unwrap()
+            }
+        }
+
+        if let Some(rest) = &self.rest {
+            let sep = if self.args.is_empty() { "" } else { ", " };
+            write!(&mut res, "{sep}...{rest}").unwrap();
         }
 
         write!(&mut res, "] {}", self.body.string()).unwrap();
 
         res
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
+// Builtins get their arguments already evaluated and checked against arity
+pub type BuiltinFn = fn(&[Value]) -> Result<Value>;
+
 #[derive(Clone)] //TMP1
-pub struct Builtin { //TODO
+pub struct Builtin {
     name: String,
-    body: fn(String)
+    arity: usize,
+    body: BuiltinFn,
+    span: Span
 }
 
 impl Builtin {
-    pub fn new(name: String, body: fn(String)) -> Builtin {
-        Builtin { name, body }
+    pub fn new(name: String, arity: usize, body: BuiltinFn, span: Span) -> Builtin {
+        Builtin { name, arity, body, span }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn call(&self, args: Vec<Value>, named: Vec<(String, Value)>) -> Result<Value> {
+        // Builtin arguments have no names to give
+        if let Some((arg, _)) = named.into_iter().next() {
+            return Err(ErrorKind::UnknownArgument { function: self.name.as_str().into(), arg }.into());
+        }
+
+        if self.arity != VARIADIC && args.len() != self.arity {
+            return Err(ErrorKind::ArityMismatch {
+                name: self.name.as_str().into(),
+                min: self.arity,
+                max: self.arity,
+                found: args.len()
+            }.into());
+        }
+
+        (self.body)(&args)
     }
 }
 
 impl Expr for Builtin {
-    fn value(&self, _bindings: &mut Bindings) -> Rc<dyn Expr> {
-        Rc::new(self.clone()) // !1
+    fn value(&self, _bindings: &mut Bindings) -> Result<Value> {
+        Ok(Value::Builtin(Rc::new(self.clone()))) // !1
     }
 
     fn string(&self) -> String {
         format!("<BUILTIN {}>", self.name)
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+// Errors raised here have no span of their own, the caller knows where the call is
+pub fn call(callee: Value, args: Vec<Value>, named: Vec<(String, Value)>, bindings: &Bindings, site: Option<&Span>) -> Result<Value> {
+    let function = match callee {
+        Value::Function(function) => function,
+        Value::Builtin(builtin) => return builtin.call(args, named),
+        value => return Err(ErrorKind::NotCallable(value.type_name()).into())
+    };
+
+    let stack = bindings.stack();
+    stack.push(TraceEntry::new(function.name.clone(), site.cloned()))?;
+
+    let res = run(function, args, named, bindings).map_err(|mut err| {
+        // The innermost call sees the whole stack, outer calls keep what it recorded
+        if err.trace.is_empty() {
+            err.trace = stack.trace();
+        }
+        err
+    });
+
+    stack.pop();
+    bindings.collector().collect_if_due();
+    res
+}
+
+// A tail call takes over the stack entry of the function that made it and runs in this
+// loop, so a chain of them needs no more room than one call. Errors it raises without a
+// span of their own get the span of the call in the RETURN
+fn run(mut function: Rc<Function>, mut args: Vec<Value>, mut named: Vec<(String, Value)>, bindings: &Bindings) -> Result<Value> {
+    let mut site: Option<Span> = None;
+
+    loop {
+        let mut function_bindings = bindings.new_within(&function.scope);
+        function_bindings.new_frame();
+
+        let flow = function.bind(args, named, &mut function_bindings)
+            .and_then(|_| function.body.execute(&mut function_bindings))
+            .map_err(|err| match &site {
+                Some(site) => err.at(site),
+                None => err
+            })?;
+
+        let tail = match flow {
+            Flow::Next => return Ok(Value::Unit),
+            Flow::Return(value) => return Ok(value),
+            Flow::Break(label) => return Err(ErrorKind::OutsideLoop { keyword: "BREAK", label }.into()),
+            Flow::Continue(label) => return Err(ErrorKind::OutsideLoop { keyword: "CONTINUE", label }.into()),
+            Flow::Tail(tail) => *tail
+        };
+
+        function = match tail.callee {
+            Value::Function(function) => function,
+            Value::Builtin(builtin) => return builtin.call(tail.args, tail.named).map_err(|err| err.at(&tail.site)),
+            value => return Err(Error::new(ErrorKind::NotCallable(value.type_name()), &tail.site))
+        };
+
+        bindings.stack().replace(TraceEntry::new(function.name.clone(), Some(tail.site.clone())));
+        (args, named, site) = (tail.args, tail.named, Some(tail.site));
+    }
+}
+
+// An argument passed by the name the function gives it
+pub type NamedArg = (String, Rc<dyn Expr>);
+
+// A positional argument, or a list or tuple whose items are passed one by one
+#[derive(Clone)]
+pub enum Arg {
+    One(Rc<dyn Expr>),
+    Spread(Rc<dyn Expr>)
 }
 
+impl Arg {
+    fn string(&self) -> String {
+        match self {
+            Arg::One(expr) => expr.string(),
+            Arg::Spread(expr) => format!("...{}", expr.string())
+        }
+    }
+}
+
+// Named arguments come after the positional ones, the parser already rejects repeats
 pub struct CallExpr<F: Expr> {
     expr: F,
-    args: Vec<Rc<dyn Expr>>
+    args: Vec<Arg>,
+    named: Vec<NamedArg>,
+    span: Span
 }
 
 impl<F: Expr> CallExpr<F> {
-    pub fn new(expr: F, args: Vec<Rc<dyn Expr>>) -> CallExpr<F> {
-        CallExpr { expr, args }
+    pub fn new(expr: F, args: Vec<Arg>, span: Span) -> CallExpr<F> {
+        CallExpr { expr, args, named: Vec::new(), span }
+    }
+
+    pub fn named(mut self, named: Vec<NamedArg>) -> CallExpr<F> {
+        self.named = named;
+        self
+    }
+
+    // Evaluates the callee and then the arguments, everything the call needs to be made
+    fn prepare(&self, bindings: &mut Bindings) -> Result<TailCall> {
+        let callee = self.expr.value(bindings)?;
+
+        let mut args = Vec::new();
+        for arg in &self.args {
+            match arg {
+                Arg::One(expr) => args.push(expr.value(bindings)?),
+                Arg::Spread(expr) => match expr.value(bindings)? {
+                    Value::List(list) => args.extend(list.borrow().iter().cloned()),
+                    Value::Tuple(items) => args.extend(items.iter().cloned()),
+                    value => return Err(Error::new(ErrorKind::TypeMismatch { expected: "LIST", found: value.type_name() }, expr.span()))
+                }
+            }
+        }
+
+        let mut named = Vec::new();
+        for (name, arg) in &self.named {
+            named.push((name.clone(), arg.value(bindings)?));
+        }
+
+        Ok(TailCall { callee, args, named, site: self.span.clone() })
     }
 }
 
 impl<F: Expr> Expr for CallExpr<F> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let function = (self.expr.value(bindings) as Rc<dyn Any>).downcast::<Function>();
-
-        if let Ok(function) = function {
-            let mut function_bindings = bindings.new_with_globals();
-            
-            function_bindings.new_frame();
-            for i in 0..self.args.len() {
-                function_bindings.add(function.args[i].clone(), self.args[i].value(bindings));
-            }
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let TailCall { callee, args, named, .. } = self.prepare(bindings)?;
+        call(callee, args, named, bindings, Some(&self.span)).map_err(|err| err.at(&self.span))
+    }
+
+    fn tail(&self, bindings: &mut Bindings) -> Result<Flow> {
+        Ok(Flow::Tail(Box::new(self.prepare(bindings)?)))
+    }
+
+    fn string(&self) -> String {
+        let mut args: Vec<String> = self.args.iter().map(|arg| arg.string()).collect();
+        args.extend(self.named.iter().map(|(name, arg)| format!("{name}: {}", arg.string())));
 
-            function.body.execute(&mut function_bindings);
+        format!("{}({})", postfix_operand(&self.expr), args.join(", "))
+    }
 
-            return function_bindings.get(&function.name);
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Program;
+    use crate::parser::{parse, Parser};
+
+    // The value of expr once source is loaded, or the error it raises
+    fn eval(source: &str, expr: &str) -> Result<String, String> {
+        let mut program = Program::new();
+        for def in parse(source, "test").unwrap() {
+            program.add(def);
         }
+        program.load().unwrap();
 
-        let builtin = (self.expr.value(bindings) as Rc<dyn Any>).downcast::<Builtin>();
+        let expr = Parser::new(expr, "test").unwrap().parse_expr().unwrap();
+        program.eval(&expr).map(|value| value.string()).map_err(|err| err.kind.to_string())
+    }
 
-        if let Ok(builtin) = builtin {
-            let arg = (self.args[0].value(bindings) as Rc<dyn Any>).downcast::<IntExpr>().unwrap();
-            (builtin.body)(format!("{}", arg.0));
+    fn ok(value: &str) -> Result<String, String> {
+        Ok(value.to_string())
+    }
+
+    fn err(message: &str) -> Result<String, String> {
+        Err(message.to_string())
+    }
 
-            return Rc::new(IntExpr::new(0));
+    const DEFAULTS: &str = "
+        DEFINE box := FUNCTION[x, y := x * 2, z := y + 1] {
+            RETURN (x, y, z)
         }
+    ";
 
-        panic!("NOT A FUNCTION");
+    #[test]
+    fn defaults_see_the_arguments_before_them() {
+        assert_eq!(eval(DEFAULTS, "box(1)"), ok("(1, 2, 3)"));
+        assert_eq!(eval(DEFAULTS, "box(1, 5)"), ok("(1, 5, 6)"));
+        assert_eq!(eval(DEFAULTS, "box(1, z: 0)"), ok("(1, 2, 0)"));
+        assert_eq!(eval(DEFAULTS, "box(y: 1, x: 4)"), ok("(4, 1, 2)"));
     }
 
-    fn string(&self) -> String {
-        let mut res = "CALL[".to_string();
+    #[test]
+    fn arity_errors_give_the_accepted_range() {
+        assert_eq!(eval(DEFAULTS, "box()"), err("box TAKES 1 TO 3 ARGUMENTS, GIVEN 0"));
+        assert_eq!(eval(DEFAULTS, "box(1, 2, 3, 4)"), err("box TAKES 1 TO 3 ARGUMENTS, GIVEN 4"));
+        assert_eq!(eval("DEFINE f := FUNCTION[a] { RETURN a }", "f(1, 2)"), err("f TAKES 1 ARGUMENTS, GIVEN 2"));
+        assert_eq!(eval("", "len()"), err("len TAKES 1 ARGUMENTS, GIVEN 0"));
+    }
 
-        for (i, arg) in self.args.iter().enumerate() {
-            if i == 0 {
-                write!(&mut res, "{}", arg.string()).unwrap();
-            } else {
-                write!(&mut res, ", {}", arg.string()).unwrap();
-            }
+    #[test]
+    fn named_argument_errors() {
+        assert_eq!(eval(DEFAULTS, "box(1, w: 2)"), err("box HAS NO ARGUMENT w"));
+        assert_eq!(eval(DEFAULTS, "box(1, x: 2)"), err("ARGUMENT x OF box GIVEN TWICE"));
+        assert_eq!(eval(DEFAULTS, "box(y: 2)"), err("ARGUMENT x OF box NOT GIVEN"));
+    }
+
+    const REST: &str = "
+        DEFINE pair := FUNCTION[a, b := 10, ...more] {
+            RETURN (a, b, more)
         }
+    ";
+
+    #[test]
+    fn rest_takes_what_is_left_over() {
+        assert_eq!(eval(REST, "pair(1)"), ok("(1, 10, [])"));
+        assert_eq!(eval(REST, "pair(1, 2, 3, 4)"), ok("(1, 2, [3, 4])"));
+        assert_eq!(eval(REST, "pair(...[7, 8, 9])"), ok("(7, 8, [9])"));
+        assert_eq!(eval(REST, "pair()"), err("pair TAKES AT LEAST 1 ARGUMENTS, GIVEN 0"));
+    }
+
+    #[test]
+    fn rest_with_named_arguments() {
+        assert_eq!(eval(REST, "pair(a: 1)"), ok("(1, 10, [])"));
+        assert_eq!(eval(REST, "pair(1, 2, 3, b: 4)"), err("ARGUMENT b OF pair GIVEN TWICE"));
+        assert_eq!(eval(REST, "pair(1, more: [2])"), err("pair HAS NO ARGUMENT more"));
+    }
 
-        res + "]"
+    #[test]
+    fn spreading_takes_lists_and_tuples_only() {
+        assert_eq!(eval(REST, "pair(0, ...(1, 2), ...[], ...[3])"), ok("(0, 1, [2, 3])"));
+        assert_eq!(eval(REST, "pair(...5)"), err("EXPECTED LIST, FOUND INT"));
+        assert_eq!(eval(REST, "pair(...{1: 2})"), err("EXPECTED LIST, FOUND MAP"));
     }
 }
@@ -0,0 +1,529 @@
+use crate::parser::ParseError;
+use crate::span::Span;
+use std::rc::Rc;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Ident(String),
+    Int(String),
+    Float(String),
+    Text(String),
+    Define,
+    Function,
+    True,
+    False,
+    Unit,
+    And,
+    Or,
+    Not,
+    If,
+    Then,
+    Else,
+    While,
+    For,
+    To,
+    Step,
+    Break,
+    Continue,
+    Return,
+    Record,
+    New,
+    Union,
+    Match,
+    Assign,
+    Change,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Eq,
+    Lt,
+    Le,
+    Ge,
+    Gt,
+    Amp,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Comma,
+    Colon,
+    Dot,
+    Ellipsis,
+    Newline,
+    Eof
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "IDENT {name}"),
+            Token::Int(n) => write!(f, "INT {n}"),
+            Token::Float(n) => write!(f, "FLOAT {n}"),
+            Token::Text(s) => write!(f, "TEXT {s:?}"),
+            Token::Define => write!(f, "DEFINE"),
+            Token::Function => write!(f, "FUNCTION"),
+            Token::True => write!(f, "TRUE"),
+            Token::False => write!(f, "FALSE"),
+            Token::Unit => write!(f, "UNIT"),
+            Token::And => write!(f, "AND"),
+            Token::Or => write!(f, "OR"),
+            Token::Not => write!(f, "NOT"),
+            Token::If => write!(f, "IF"),
+            Token::Then => write!(f, "THEN"),
+            Token::Else => write!(f, "ELSE"),
+            Token::While => write!(f, "WHILE"),
+            Token::For => write!(f, "FOR"),
+            Token::To => write!(f, "TO"),
+            Token::Step => write!(f, "STEP"),
+            Token::Break => write!(f, "BREAK"),
+            Token::Continue => write!(f, "CONTINUE"),
+            Token::Return => write!(f, "RETURN"),
+            Token::Record => write!(f, "RECORD"),
+            Token::New => write!(f, "NEW"),
+            Token::Union => write!(f, "UNION"),
+            Token::Match => write!(f, "MATCH"),
+            Token::Assign => write!(f, "':='"),
+            Token::Change => write!(f, "'='"),
+            Token::Plus => write!(f, "'+'"),
+            Token::Minus => write!(f, "'-'"),
+            Token::Star => write!(f, "'*'"),
+            Token::Slash => write!(f, "'/'"),
+            Token::Percent => write!(f, "'%'"),
+            Token::Eq => write!(f, "'=='"),
+            Token::Lt => write!(f, "'<'"),
+            Token::Le => write!(f, "'<='"),
+            Token::Ge => write!(f, "'>='"),
+            Token::Gt => write!(f, "'>'"),
+            Token::Amp => write!(f, "'&'"),
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+            Token::LBracket => write!(f, "'['"),
+            Token::RBracket => write!(f, "']'"),
+            Token::LBrace => write!(f, "'{{'"),
+            Token::RBrace => write!(f, "'}}'"),
+            Token::Comma => write!(f, "','"),
+            Token::Colon => write!(f, "':'"),
+            Token::Dot => write!(f, "'.'"),
+            Token::Ellipsis => write!(f, "'...'"),
+            Token::Newline => write!(f, "NEWLINE"),
+            Token::Eof => write!(f, "EOF")
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Lexeme {
+    pub token: Token,
+    pub span: Span
+}
+
+impl Lexeme {
+    fn new(token: Token, span: Span) -> Lexeme {
+        Lexeme { token, span }
+    }
+}
+
+fn keyword(word: &str) -> Option<Token> {
+    match word {
+        "DEFINE" => Some(Token::Define),
+        "FUNCTION" => Some(Token::Function),
+        "TRUE" => Some(Token::True),
+        "FALSE" => Some(Token::False),
+        "UNIT" => Some(Token::Unit),
+        "AND" => Some(Token::And),
+        "OR" => Some(Token::Or),
+        "NOT" => Some(Token::Not),
+        "IF" => Some(Token::If),
+        "THEN" => Some(Token::Then),
+        "ELSE" => Some(Token::Else),
+        "WHILE" => Some(Token::While),
+        "FOR" => Some(Token::For),
+        "TO" => Some(Token::To),
+        "STEP" => Some(Token::Step),
+        "BREAK" => Some(Token::Break),
+        "CONTINUE" => Some(Token::Continue),
+        "RETURN" => Some(Token::Return),
+        "RECORD" => Some(Token::Record),
+        "NEW" => Some(Token::New),
+        "UNION" => Some(Token::Union),
+        "MATCH" => Some(Token::Match),
+        _ => None
+    }
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    file: Rc<str>,
+    line: usize,
+    col: usize,
+    nesting: Vec<char>,
+    lexemes: Vec<Lexeme>
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str, file: &str) -> Lexer<'a> {
+        Lexer {
+            chars: source.chars().peekable(),
+            file: file.into(),
+            line: 1,
+            col: 1,
+            nesting: Vec::new(),
+            lexemes: Vec::new()
+        }
+    }
+
+    fn span(&self, line: usize, col: usize) -> Span {
+        Span::new(Rc::clone(&self.file), line, col, 1)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+
+        if c == '\n' {
+            self.line += 1;
+            self.col = 1;
+        } else {
+            self.col += 1;
+        }
+
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn push(&mut self, token: Token, line: usize, col: usize) {
+        // Newlines separate statements, so collapse runs of them and drop the ones inside brackets
+        if token == Token::Newline {
+            let last = self.lexemes.last().map(|x| &x.token);
+            let bracketed = matches!(self.nesting.last(), Some('(' | '['));
+            if bracketed || last.is_none() || last == Some(&Token::Newline) {
+                return;
+            }
+        }
+
+        let mut span = self.span(line, col);
+        if self.line == line {
+            span.len = (self.col - col).max(1);
+        }
+
+        self.lexemes.push(Lexeme::new(token, span));
+    }
+
+    fn text(&mut self, line: usize, col: usize) -> Result<Token, ParseError> {
+        let mut res = String::new();
+
+        loop {
+            let c = match self.bump() {
+                Some(c) => c,
+                None => return Err(ParseError::eof("UNTERMINATED TEXT LITERAL", self.span(line, col)))
+            };
+
+            match c {
+                '"' => return Ok(Token::Text(res)),
+                '\\' => {
+                    let (esc_line, esc_col) = (self.line, self.col);
+                    let escaped = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some('\\') => '\\',
+                        Some('"') => '"',
+                        Some('\'') => '\'',
+                        Some('u') => self.unicode(esc_line, esc_col)?,
+                        Some(c) => {
+                            return Err(ParseError::new(format!("UNKNOWN ESCAPE \\{c}"), self.span(esc_line, esc_col)));
+                        }
+                        None => return Err(ParseError::eof("UNTERMINATED TEXT LITERAL", self.span(line, col)))
+                    };
+                    res.push(escaped);
+                }
+                c => res.push(c)
+            }
+        }
+    }
+
+    fn digits(&mut self, res: &mut String) {
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_digit() { break; }
+            res.push(c);
+            self.bump();
+        }
+    }
+
+    // A fraction or an exponent needs a digit after it, otherwise the dot or the e is left alone
+    fn number(&mut self) -> Token {
+        let mut res = String::new();
+        let mut float = false;
+        self.digits(&mut res);
+
+        let mut ahead = self.chars.clone();
+        if ahead.next() == Some('.') && ahead.next().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+            res.push('.');
+            self.digits(&mut res);
+            float = true;
+        }
+
+        let mut ahead = self.chars.clone();
+        if matches!(ahead.next(), Some('e' | 'E')) {
+            let sign = ahead.next_if(|c| matches!(c, '+' | '-'));
+
+            if ahead.next().is_some_and(|c| c.is_ascii_digit()) {
+                self.bump();
+                res.push('e');
+                if let Some(sign) = sign {
+                    self.bump();
+                    res.push(sign);
+                }
+                self.digits(&mut res);
+                float = true;
+            }
+        }
+
+        if float { Token::Float(res) } else { Token::Int(res) }
+    }
+
+    fn unicode(&mut self, line: usize, col: usize) -> Result<char, ParseError> {
+        if !self.eat('{') {
+            return Err(ParseError::new("EXPECTED '{' AFTER \\u".to_string(), self.span(line, col)));
+        }
+
+        let mut digits = String::new();
+        while let Some(c) = self.peek() {
+            if c == '}' { break; }
+            digits.push(c);
+            self.bump();
+        }
+
+        if !self.eat('}') {
+            return Err(ParseError::eof("UNTERMINATED UNICODE ESCAPE", self.span(line, col)));
+        }
+
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| ParseError::new(format!("INVALID UNICODE ESCAPE \\u{{{digits}}}"), self.span(line, col)))
+    }
+
+    fn run(mut self) -> Result<Vec<Lexeme>, ParseError> {
+        while let Some(c) = self.peek() {
+            let (line, col) = (self.line, self.col);
+
+            if c == '\n' || c == ';' {
+                self.bump();
+                self.push(Token::Newline, line, col);
+                continue;
+            }
+
+            if c.is_whitespace() {
+                self.bump();
+                continue;
+            }
+
+            if c == '#' {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.bump();
+                }
+                continue;
+            }
+
+            if c.is_ascii_digit() {
+                let token = self.number();
+                self.push(token, line, col);
+                continue;
+            }
+
+            if c.is_alphabetic() || c == '_' {
+                let mut word = String::new();
+                while let Some(c) = self.peek() {
+                    if !c.is_alphanumeric() && c != '_' { break; }
+                    word.push(c);
+                    self.bump();
+                }
+
+                let token = keyword(&word).unwrap_or(Token::Ident(word));
+                self.push(token, line, col);
+                continue;
+            }
+
+            self.bump();
+            let token = match c {
+                '"' => self.text(line, col)?,
+                ':' if self.eat('=') => Token::Assign,
+                ':' => Token::Colon,
+                '=' if self.eat('=') => Token::Eq,
+                '=' => Token::Change,
+                '<' if self.eat('=') => Token::Le,
+                '<' => Token::Lt,
+                '>' if self.eat('=') => Token::Ge,
+                '>' => Token::Gt,
+                '+' => Token::Plus,
+                '-' => Token::Minus,
+                '*' => Token::Star,
+                '/' => Token::Slash,
+                '%' => Token::Percent,
+                '&' => Token::Amp,
+                ',' => Token::Comma,
+                '.' if self.eat('.') => {
+                    if !self.eat('.') {
+                        return Err(ParseError::new("EXPECTED '...'".to_string(), self.span(line, col)));
+                    }
+                    Token::Ellipsis
+                }
+                '.' => Token::Dot,
+                '(' | '[' | '{' => {
+                    self.nesting.push(c);
+                    match c {
+                        '(' => Token::LParen,
+                        '[' => Token::LBracket,
+                        _ => Token::LBrace
+                    }
+                }
+                ')' | ']' | '}' => {
+                    self.nesting.pop();
+                    match c {
+                        ')' => Token::RParen,
+                        ']' => Token::RBracket,
+                        _ => Token::RBrace
+                    }
+                }
+                c => return Err(ParseError::new(format!("UNEXPECTED CHARACTER {c:?}"), self.span(line, col)))
+            };
+
+            self.push(token, line, col);
+        }
+
+        let (line, col) = (self.line, self.col);
+        self.push(Token::Newline, line, col);
+        let span = self.span(line, col);
+        self.lexemes.push(Lexeme::new(Token::Eof, span));
+
+        Ok(self.lexemes)
+    }
+}
+
+pub fn tokenize(source: &str, file: &str) -> Result<Vec<Lexeme>, ParseError> {
+    Lexer::new(source, file).run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source, "test").unwrap().into_iter().map(|lexeme| lexeme.token).collect()
+    }
+
+    fn error(source: &str) -> (String, usize, usize) {
+        let err = tokenize(source, "test").unwrap_err();
+        (err.message, err.span.line, err.span.col)
+    }
+
+    fn int(n: &str) -> Token {
+        Token::Int(n.to_string())
+    }
+
+    fn float(n: &str) -> Token {
+        Token::Float(n.to_string())
+    }
+
+    #[test]
+    fn numbers_with_fractions_and_exponents() {
+        assert_eq!(tokens("42"), [int("42"), Token::Newline, Token::Eof]);
+        assert_eq!(tokens("1.5"), [float("1.5"), Token::Newline, Token::Eof]);
+        assert_eq!(tokens("1e10"), [float("1e10"), Token::Newline, Token::Eof]);
+        assert_eq!(tokens("2.5E-3"), [float("2.5e-3"), Token::Newline, Token::Eof]);
+        assert_eq!(tokens("7e+2"), [float("7e+2"), Token::Newline, Token::Eof]);
+    }
+
+    #[test]
+    fn dots_and_es_without_digits_are_left_alone() {
+        let ident = |name: &str| Token::Ident(name.to_string());
+
+        assert_eq!(tokens("1.x"), [int("1"), Token::Dot, ident("x"), Token::Newline, Token::Eof]);
+        assert_eq!(tokens("1..."), [int("1"), Token::Ellipsis, Token::Newline, Token::Eof]);
+        assert_eq!(tokens("3e"), [int("3"), ident("e"), Token::Newline, Token::Eof]);
+        assert_eq!(tokens("3e+"), [int("3"), ident("e"), Token::Plus, Token::Newline, Token::Eof]);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(tokens(r#""a\tb\n\"\\\'\0\r""#)[0], Token::Text("a\tb\n\"\\'\0\r".to_string()));
+        assert_eq!(tokens(r#""\u{48}\u{1F600}""#)[0], Token::Text("H\u{1F600}".to_string()));
+    }
+
+    #[test]
+    fn bad_escapes_point_at_themselves() {
+        assert_eq!(error(r#"x := "ab\q""#), ("UNKNOWN ESCAPE \\q".to_string(), 1, 10));
+        assert_eq!(error(r#""\u{110000}""#), ("INVALID UNICODE ESCAPE \\u{110000}".to_string(), 1, 3));
+        assert_eq!(error(r#""\u48""#), ("EXPECTED '{' AFTER \\u".to_string(), 1, 3));
+    }
+
+    #[test]
+    fn unterminated_text_points_at_its_start() {
+        let err = tokenize("x := 1\ny := \"abc", "test").unwrap_err();
+        assert!(err.eof);
+        assert_eq!((err.message.as_str(), err.span.line, err.span.col), ("UNTERMINATED TEXT LITERAL", 2, 6));
+    }
+
+    #[test]
+    fn newlines_inside_brackets_are_dropped() {
+        let source = "f(1,\n2)\n[3,\n4]\n{\n5\n}";
+        assert_eq!(tokens(source), [
+            Token::Ident("f".to_string()), Token::LParen, int("1"), Token::Comma, int("2"), Token::RParen, Token::Newline,
+            Token::LBracket, int("3"), Token::Comma, int("4"), Token::RBracket, Token::Newline,
+            Token::LBrace, Token::Newline, int("5"), Token::Newline, Token::RBrace, Token::Newline,
+            Token::Eof
+        ]);
+    }
+
+    #[test]
+    fn braces_inside_brackets_keep_their_newlines() {
+        let source = "f(FUNCTION[] {\nx\n})";
+        assert_eq!(tokens(source), [
+            Token::Ident("f".to_string()), Token::LParen, Token::Function, Token::LBracket, Token::RBracket,
+            Token::LBrace, Token::Newline, Token::Ident("x".to_string()), Token::Newline, Token::RBrace,
+            Token::RParen, Token::Newline, Token::Eof
+        ]);
+    }
+
+    #[test]
+    fn runs_of_newlines_and_comments_collapse() {
+        assert_eq!(tokens("\n\n# comment\n1;;\n\n2 # more\n"), [
+            int("1"), Token::Newline, int("2"), Token::Newline, Token::Eof
+        ]);
+    }
+
+    #[test]
+    fn spans_cover_each_token() {
+        let spans: Vec<(usize, usize, usize)> = tokenize("x := 1.25\n  foo(\"hi\")", "test").unwrap()
+            .into_iter()
+            .map(|lexeme| (lexeme.span.line, lexeme.span.col, lexeme.span.len))
+            .collect();
+
+        assert_eq!(spans[..7], [(1, 1, 1), (1, 3, 2), (1, 6, 4), (1, 10, 1), (2, 3, 3), (2, 6, 1), (2, 7, 4)]);
+    }
+
+    #[test]
+    fn unexpected_characters() {
+        assert_eq!(error("x := 1\n  y @ 2"), ("UNEXPECTED CHARACTER '@'".to_string(), 2, 5));
+        assert_eq!(error("xs..1"), ("EXPECTED '...'".to_string(), 1, 3));
+    }
+}
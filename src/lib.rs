@@ -0,0 +1,16 @@
+pub mod prelude;
+pub mod core;
+pub mod collector;
+pub mod value;
+pub mod bigint;
+pub mod expressions;
+pub mod operations;
+pub mod statements;
+pub mod records;
+pub mod unions;
+pub mod builtins;
+pub mod error;
+pub mod span;
+pub mod lexer;
+pub mod parser;
+pub mod repl;
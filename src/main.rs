@@ -1,41 +1,177 @@
-#![feature(trait_upcasting)]
+use interpreter::prelude::*;
+use interpreter::lexer::tokenize;
+use interpreter::parser::{parse, ParseError};
+use interpreter::span::Sources;
+use interpreter::repl::Repl;
+use std::process::ExitCode;
 
-mod prelude;
-mod core;
-mod expressions;
-mod operations;
-mod statements;
+const EXIT_USAGE: u8 = 64;
+const EXIT_SYNTAX: u8 = 65;
+const EXIT_NO_INPUT: u8 = 66;
+const EXIT_RUNTIME: u8 = 70;
 
-use crate::prelude::*;
-use std::rc::Rc;
+// Programs run on a thread with STACK_SIZE bytes of stack, enough for CLI_RECURSION_LIMIT calls
+// of fairly nested functions even in a debug build
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+const CLI_RECURSION_LIMIT: usize = 10_000;
+
+const USAGE: &str = "\
+USAGE:
+    interpreter run <FILE> [--dump-tokens] [--dump-ast] [--checked | --wrapping | --saturating]
+    interpreter check <FILE> [--dump-tokens] [--dump-ast]
+    interpreter [repl]
+
+COMMANDS:
+    run      Parse FILE and call its main function
+    check    Parse FILE without running it
+    repl     Start an interactive session (the default)
+
+OPTIONS:
+    --dump-tokens    Print the tokens of FILE
+    --dump-ast       Print the parsed definitions of FILE
+    --checked        Fail when int arithmetic leaves the 128-bit range instead of growing
+    --wrapping       Wrap int arithmetic around at the 128-bit range instead of growing
+    --saturating     Clamp int arithmetic to the 128-bit range instead of growing";
+
+#[derive(PartialEq)]
+enum Command {
+    Run,
+    Check,
+    Repl
+}
+
+struct Options {
+    command: Command,
+    path: Option<String>,
+    dump_tokens: bool,
+    dump_ast: bool,
+    overflow: Overflow
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let command = match args.next().as_deref() {
+            Some("run") => Command::Run,
+            Some("check") => Command::Check,
+            Some("repl") | None => Command::Repl,
+            Some(other) => return Err(format!("UNKNOWN COMMAND {other}"))
+        };
+
+        let mut path = None;
+        let mut dump_tokens = false;
+        let mut dump_ast = false;
+        let mut overflow = None;
+
+        for arg in args {
+            match arg.as_str() {
+                "--dump-tokens" => dump_tokens = true,
+                "--dump-ast" => dump_ast = true,
+                "--checked" | "--wrapping" | "--saturating" if overflow.is_some() => {
+                    return Err("ONLY ONE OF --checked, --wrapping AND --saturating MAY BE GIVEN".to_string());
+                }
+                "--checked" => overflow = Some(Overflow::Checked),
+                "--wrapping" => overflow = Some(Overflow::Wrapping),
+                "--saturating" => overflow = Some(Overflow::Saturating),
+                flag if flag.starts_with("--") => return Err(format!("UNKNOWN OPTION {flag}")),
+                _ if path.is_some() => return Err(format!("UNEXPECTED ARGUMENT {arg}")),
+                _ => path = Some(arg)
+            }
+        }
+
+        if command == Command::Repl && (path.is_some() || dump_tokens || dump_ast || overflow.is_some()) {
+            return Err("repl TAKES NO ARGUMENTS".to_string());
+        }
+
+        if command != Command::Repl && path.is_none() {
+            return Err("MISSING FILE".to_string());
+        }
+
+        Ok(Options { command, path, dump_tokens, dump_ast, overflow: overflow.unwrap_or_default() })
+    }
+}
+
+fn syntax_error(sources: &Sources, err: ParseError) -> ExitCode {
+    eprintln!("{}", err.render(sources));
+    ExitCode::from(EXIT_SYNTAX)
+}
+
+fn main() -> ExitCode {
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(start)
+        .expect("CANNOT START THE INTERPRETER THREAD")
+        .join()
+        .unwrap_or(ExitCode::FAILURE)
+}
+
+fn start() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let path = match options.path {
+        Some(path) => path,
+        None => {
+            let mut repl = Repl::new();
+            repl.set_recursion_limit(CLI_RECURSION_LIMIT);
+            repl.run();
+            return ExitCode::SUCCESS;
+        }
+    };
+
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("CANNOT READ {path}: {err}");
+            return ExitCode::from(EXIT_NO_INPUT);
+        }
+    };
+
+    let mut sources = Sources::new();
+    sources.add(&path, &source);
+
+    if options.dump_tokens {
+        match tokenize(&source, &path) {
+            Ok(lexemes) => {
+                for lexeme in lexemes {
+                    println!("{}:{} {}", lexeme.span.line, lexeme.span.col, lexeme.token);
+                }
+            }
+            Err(err) => return syntax_error(&sources, err)
+        }
+    }
+
+    let definitions = match parse(&source, &path) {
+        Ok(definitions) => definitions,
+        Err(err) => return syntax_error(&sources, err)
+    };
+
+    if options.dump_ast {
+        for def in &definitions {
+            println!("{}", def.string());
+        }
+    }
+
+    if options.command == Command::Check {
+        return ExitCode::SUCCESS;
+    }
 
-fn main() {
     let mut program = Program::new();
+    program.set_overflow(options.overflow);
+    program.set_recursion_limit(CLI_RECURSION_LIMIT);
+    for def in definitions {
+        program.add(def);
+    }
 
-    program.add(
-        define(
-            "main",
-            &[],
-            &[
-                Rc::new(eval(call(var("print"), &[Rc::new(call(var("bar"), &[Rc::new(int("5"))]))])))
-            ]
-        )
-    );
-
-    program.add(
-        r#const("foo", int("5"))
-    );
-
-    program.add(
-        define(
-            "bar",
-            &["x"],
-            &[
-                Rc::new(add_var("y", mul(var("x"), var("x")))),
-                Rc::new(add_var("bar", add(var("y"), int("1")))),
-            ]
-        )
-    );
-
-    program.run();
+    match program.run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err.render(&sources));
+            ExitCode::from(EXIT_RUNTIME)
+        }
+    }
 }
@@ -1,307 +1,668 @@
-use crate::core::{Expr, Bindings};
-use crate::expressions::{IntExpr, BoolExpr};
+use crate::core::{Expr, Bindings, Flow, Overflow};
+use crate::value::Value;
+use crate::bigint::BigInt;
+use crate::error::{Error, ErrorKind, Result};
+use crate::span::Span;
+use std::cmp::Ordering;
 use std::rc::Rc;
-use std::any::Any;
+
+// Ints stay ints, a big int on either side makes both big, a float on either side
+// turns both sides into floats
+enum Numbers {
+    Ints(i128, i128),
+    Bigs(BigInt, BigInt),
+    Floats(f64, f64)
+}
+
+fn big(value: &Value) -> Option<BigInt> {
+    match value {
+        Value::Int(n) => Some(BigInt::from(*n)),
+        Value::Big(n) => Some(BigInt::clone(n)),
+        _ => None
+    }
+}
+
+fn float(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(n) => Some(*n as f64),
+        Value::Big(n) => Some(n.to_f64()),
+        Value::Float(n) => Some(*n),
+        _ => None
+    }
+}
+
+fn numbers(left: Value, right: Value, left_span: &Span, right_span: &Span) -> Result<Numbers> {
+    match (left, right) {
+        (Value::Int(x), Value::Int(y)) => Ok(Numbers::Ints(x, y)),
+        (x @ (Value::Int(_) | Value::Big(_)), y @ (Value::Int(_) | Value::Big(_))) => {
+            Ok(Numbers::Bigs(big(&x).unwrap(), big(&y).unwrap()))
+        }
+        (x @ (Value::Int(_) | Value::Big(_) | Value::Float(_)), y @ (Value::Int(_) | Value::Big(_) | Value::Float(_))) => {
+            Ok(Numbers::Floats(float(&x).unwrap(), float(&y).unwrap()))
+        }
+        (Value::Int(_) | Value::Big(_) | Value::Float(_), y) => {
+            Err(Error::new(ErrorKind::TypeMismatch { expected: "NUMBER", found: y.type_name() }, right_span))
+        }
+        (x, _) => Err(Error::new(ErrorKind::TypeMismatch { expected: "NUMBER", found: x.type_name() }, left_span))
+    }
+}
+
+fn operands<Lhs: Expr, Rhs: Expr>(left: &Lhs, right: &Rhs, bindings: &mut Bindings) -> Result<Numbers> {
+    let x = left.value(bindings)?;
+    let y = right.value(bindings)?;
+    numbers(x, y, left.span(), right.span())
+}
+
+#[derive(Clone, Copy)]
+enum IntOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod
+}
+
+impl IntOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            IntOp::Add => "+",
+            IntOp::Sub => "-",
+            IntOp::Mul => "*",
+            IntOp::Div => "/",
+            IntOp::Mod => "%"
+        }
+    }
+}
+
+// Ints that fit an i128 take the fast path, anything else is worked out exactly and then
+// fitted according to the overflow mode. Division by zero is always an error
+fn int_op(op: IntOp, x: i128, y: i128, bindings: &Bindings, span: &Span) -> Result<Value> {
+    let checked = match op {
+        IntOp::Add => x.checked_add(y),
+        IntOp::Sub => x.checked_sub(y),
+        IntOp::Mul => x.checked_mul(y),
+        IntOp::Div => x.checked_div(y),
+        IntOp::Mod => x.checked_rem(y)
+    };
+
+    match checked {
+        Some(n) => Ok(Value::Int(n)),
+        None => big_op(op, BigInt::from(x), BigInt::from(y), bindings, span)
+    }
+}
+
+fn big_op(op: IntOp, x: BigInt, y: BigInt, bindings: &Bindings, span: &Span) -> Result<Value> {
+    let n = match op {
+        IntOp::Add => x.add(&y),
+        IntOp::Sub => x.sub(&y),
+        IntOp::Mul => x.mul(&y),
+        IntOp::Div | IntOp::Mod => match x.div_rem(&y) {
+            Some((quot, rem)) => if let IntOp::Div = op { quot } else { rem },
+            None => return Err(Error::new(ErrorKind::DivisionByZero, span))
+        }
+    };
+
+    if let Some(n) = n.to_i128() {
+        return Ok(Value::Int(n));
+    }
+
+    // Wrapping or clamping the exact result is what the i128 operations would have given
+    match bindings.overflow() {
+        Overflow::Promote => Ok(Value::Big(Rc::new(n))),
+        Overflow::Checked => Err(Error::new(ErrorKind::Overflow(op.symbol()), span)),
+        Overflow::Wrapping => Ok(Value::Int(n.wrap_i128())),
+        Overflow::Saturating => Ok(Value::Int(if n.is_negative() { i128::MIN } else { i128::MAX }))
+    }
+}
+
+fn as_int(value: Value, span: &Span) -> Result<i128> {
+    match value {
+        Value::Int(n) => Ok(n),
+        Value::Big(n) => Err(Error::new(ErrorKind::IntTooLarge(n.to_string()), span)),
+        value => Err(Error::new(ErrorKind::TypeMismatch { expected: "INT", found: value.type_name() }, span))
+    }
+}
+
+pub(crate) fn int<E: Expr>(expr: &E, bindings: &mut Bindings) -> Result<i128> {
+    as_int(expr.value(bindings)?, expr.span())
+}
+
+fn bool<E: Expr>(expr: &E, bindings: &mut Bindings) -> Result<bool> {
+    match expr.value(bindings)? {
+        Value::Bool(b) => Ok(b),
+        value => Err(Error::new(ErrorKind::TypeMismatch { expected: "BOOL", found: value.type_name() }, expr.span()))
+    }
+}
+
+// Numbers, texts, bools and units are ordered among their own kind, anything else cannot
+// be ordered. NAN is not ordered against anything, so every comparison with it is false
+fn order<Lhs: Expr, Rhs: Expr>(left: &Lhs, right: &Rhs, bindings: &mut Bindings, span: &Span) -> Result<Option<Ordering>> {
+    match (left.value(bindings)?, right.value(bindings)?) {
+        (Value::Int(x), Value::Int(y)) => Ok(Some(x.cmp(&y))),
+        (x @ (Value::Int(_) | Value::Big(_)), y @ (Value::Int(_) | Value::Big(_))) => {
+            Ok(Some(big(&x).unwrap().cmp(&big(&y).unwrap())))
+        }
+        (Value::Float(x), Value::Float(y)) => Ok(x.partial_cmp(&y)),
+        (x @ (Value::Int(_) | Value::Big(_)), Value::Float(y)) => Ok(big(&x).unwrap().cmp_f64(y)),
+        (Value::Float(x), y @ (Value::Int(_) | Value::Big(_))) => Ok(big(&y).unwrap().cmp_f64(x).map(Ordering::reverse)),
+        (Value::Text(x), Value::Text(y)) => Ok(Some(x.cmp(&y))),
+        (Value::Bool(x), Value::Bool(y)) => Ok(Some(x.cmp(&y))),
+        (Value::Unit, Value::Unit) => Ok(Some(Ordering::Equal)),
+        (x, y) => Err(Error::new(ErrorKind::Incomparable { left: x.type_name(), right: y.type_name() }, span))
+    }
+}
+
+// Used by every node that branches, so a bad condition reads the same everywhere
+pub fn condition<E: Expr>(expr: &E, bindings: &mut Bindings) -> Result<bool> {
+    match expr.value(bindings)? {
+        Value::Bool(b) => Ok(b),
+        value => Err(Error::new(ErrorKind::NonBoolCondition(value.type_name()), expr.span()))
+    }
+}
 
 pub struct AddExpr<Lhs: Expr, Rhs: Expr> {
     left: Lhs,
-    right: Rhs
+    right: Rhs,
+    span: Span
 }
 
 impl<Lhs: Expr, Rhs: Expr> AddExpr<Lhs, Rhs> {
-    pub fn new(left: Lhs, right: Rhs) -> AddExpr<Lhs, Rhs> {
-        AddExpr { left, right }
+    pub fn new(left: Lhs, right: Rhs, span: Span) -> AddExpr<Lhs, Rhs> {
+        AddExpr { left, right, span }
     }
 }
 
 impl<Lhs: Expr, Rhs: Expr> Expr for AddExpr<Lhs, Rhs> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let left = (self.left.value(bindings) as Rc<dyn Any>).downcast::<IntExpr>().unwrap();
-        let right = (self.right.value(bindings) as Rc<dyn Any>).downcast::<IntExpr>().unwrap();
-        Rc::new(IntExpr::new(left.0 + right.0))
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let left = self.left.value(bindings)?;
+        let right = self.right.value(bindings)?;
+
+        // Text only joins with text, the side that isn't is the one in the wrong
+        match (left, right) {
+            (Value::Text(x), Value::Text(y)) => Ok(Value::Text(format!("{x}{y}").into())),
+            (Value::Text(_), y) => {
+                Err(Error::new(ErrorKind::TypeMismatch { expected: "TEXT", found: y.type_name() }, self.right.span()))
+            }
+            (x, Value::Text(_)) => {
+                Err(Error::new(ErrorKind::TypeMismatch { expected: "TEXT", found: x.type_name() }, self.left.span()))
+            }
+            (x, y) => match numbers(x, y, self.left.span(), self.right.span())? {
+                Numbers::Ints(x, y) => int_op(IntOp::Add, x, y, bindings, &self.span),
+                Numbers::Bigs(x, y) => big_op(IntOp::Add, x, y, bindings, &self.span),
+                Numbers::Floats(x, y) => Ok(Value::Float(x + y))
+            }
+        }
     }
 
     fn string(&self) -> String {
         format!("({} + {})", self.left.string(), self.right.string())
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 pub struct SubExpr<Lhs: Expr, Rhs: Expr> {
     left: Lhs,
-    right: Rhs
+    right: Rhs,
+    span: Span
 }
 
 impl<Lhs: Expr, Rhs: Expr> SubExpr<Lhs, Rhs> {
-    pub fn new(left: Lhs, right: Rhs) -> SubExpr<Lhs, Rhs> {
-        SubExpr { left, right }
+    pub fn new(left: Lhs, right: Rhs, span: Span) -> SubExpr<Lhs, Rhs> {
+        SubExpr { left, right, span }
     }
 }
 
 impl<Lhs: Expr, Rhs: Expr> Expr for SubExpr<Lhs, Rhs> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let left = (self.left.value(bindings) as Rc<dyn Any>).downcast::<IntExpr>().unwrap();
-        let right = (self.right.value(bindings) as Rc<dyn Any>).downcast::<IntExpr>().unwrap();
-        Rc::new(IntExpr::new(left.0 - right.0))
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        match operands(&self.left, &self.right, bindings)? {
+            Numbers::Ints(x, y) => int_op(IntOp::Sub, x, y, bindings, &self.span),
+            Numbers::Bigs(x, y) => big_op(IntOp::Sub, x, y, bindings, &self.span),
+            Numbers::Floats(x, y) => Ok(Value::Float(x - y))
+        }
     }
 
     fn string(&self) -> String {
         format!("({} - {})", self.left.string(), self.right.string())
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 pub struct MulExpr<Lhs: Expr, Rhs: Expr> {
     left: Lhs,
-    right: Rhs
+    right: Rhs,
+    span: Span
 }
 
 impl<Lhs: Expr, Rhs: Expr> MulExpr<Lhs, Rhs> {
-    pub fn new(left: Lhs, right: Rhs) -> MulExpr<Lhs, Rhs> {
-        MulExpr { left, right }
+    pub fn new(left: Lhs, right: Rhs, span: Span) -> MulExpr<Lhs, Rhs> {
+        MulExpr { left, right, span }
     }
 }
 
 impl<Lhs: Expr, Rhs: Expr> Expr for MulExpr<Lhs, Rhs> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let left = (self.left.value(bindings) as Rc<dyn Any>).downcast::<IntExpr>().unwrap();
-        let right = (self.right.value(bindings) as Rc<dyn Any>).downcast::<IntExpr>().unwrap();
-        Rc::new(IntExpr::new(left.0 * right.0))
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        match operands(&self.left, &self.right, bindings)? {
+            Numbers::Ints(x, y) => int_op(IntOp::Mul, x, y, bindings, &self.span),
+            Numbers::Bigs(x, y) => big_op(IntOp::Mul, x, y, bindings, &self.span),
+            Numbers::Floats(x, y) => Ok(Value::Float(x * y))
+        }
     }
 
     fn string(&self) -> String {
         format!("({} * {})", self.left.string(), self.right.string())
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 pub struct DivExpr<Lhs: Expr, Rhs: Expr> {
     left: Lhs,
-    right: Rhs
+    right: Rhs,
+    span: Span
 }
 
 impl<Lhs: Expr, Rhs: Expr> DivExpr<Lhs, Rhs> {
-    pub fn new(left: Lhs, right: Rhs) -> DivExpr<Lhs, Rhs> {
-        DivExpr { left, right }
+    pub fn new(left: Lhs, right: Rhs, span: Span) -> DivExpr<Lhs, Rhs> {
+        DivExpr { left, right, span }
     }
 }
 
 impl<Lhs: Expr, Rhs: Expr> Expr for DivExpr<Lhs, Rhs> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let left = (self.left.value(bindings) as Rc<dyn Any>).downcast::<IntExpr>().unwrap();
-        let right = (self.right.value(bindings) as Rc<dyn Any>).downcast::<IntExpr>().unwrap();
-        Rc::new(if right.0 == 0 { IntExpr::new(0) } else { IntExpr::new(left.0 / right.0) })
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        match operands(&self.left, &self.right, bindings)? {
+            Numbers::Ints(x, y) => int_op(IntOp::Div, x, y, bindings, &self.span),
+            Numbers::Bigs(x, y) => big_op(IntOp::Div, x, y, bindings, &self.span),
+            Numbers::Floats(x, y) => Ok(Value::Float(x / y))
+        }
     }
 
     fn string(&self) -> String {
         format!("({} / {})", self.left.string(), self.right.string())
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 pub struct ModExpr<Lhs: Expr, Rhs: Expr> {
     left: Lhs,
-    right: Rhs
+    right: Rhs,
+    span: Span
 }
 
 impl<Lhs: Expr, Rhs: Expr> ModExpr<Lhs, Rhs> {
-    pub fn new(left: Lhs, right: Rhs) -> ModExpr<Lhs, Rhs> {
-        ModExpr { left, right }
+    pub fn new(left: Lhs, right: Rhs, span: Span) -> ModExpr<Lhs, Rhs> {
+        ModExpr { left, right, span }
     }
 }
 
 impl<Lhs: Expr, Rhs: Expr> Expr for ModExpr<Lhs, Rhs> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let left = (self.left.value(bindings) as Rc<dyn Any>).downcast::<IntExpr>().unwrap();
-        let right = (self.right.value(bindings) as Rc<dyn Any>).downcast::<IntExpr>().unwrap();
-        Rc::new(
-            if right.0 == 0 {
-                IntExpr::new(left.0)
-            } else {
-                IntExpr::new(left.0 % right.0)
-            }
-        ) 
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        match operands(&self.left, &self.right, bindings)? {
+            Numbers::Ints(x, y) => int_op(IntOp::Mod, x, y, bindings, &self.span),
+            Numbers::Bigs(x, y) => big_op(IntOp::Mod, x, y, bindings, &self.span),
+            Numbers::Floats(x, y) => Ok(Value::Float(x % y))
+        }
     }
 
     fn string(&self) -> String {
         format!("({} % {})", self.left.string(), self.right.string())
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 pub struct AndExpr<Lhs: Expr, Rhs: Expr> {
     left: Lhs,
-    right: Rhs
+    right: Rhs,
+    span: Span
 }
 
 impl<Lhs: Expr, Rhs: Expr> AndExpr<Lhs, Rhs> {
-    pub fn new(left: Lhs, right: Rhs) -> AndExpr<Lhs, Rhs> {
-        AndExpr { left, right }
+    pub fn new(left: Lhs, right: Rhs, span: Span) -> AndExpr<Lhs, Rhs> {
+        AndExpr { left, right, span }
     }
 }
 
 impl<Lhs: Expr, Rhs: Expr> Expr for AndExpr<Lhs, Rhs> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let left = (self.left.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        let right = (self.right.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        Rc::new(BoolExpr::new(left.0 && right.0))
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let left = bool(&self.left, bindings)?;
+        let right = bool(&self.right, bindings)?;
+        Ok(Value::Bool(left && right))
     }
     
     fn string(&self) -> String {
         format!("({} AND {})", self.left.string(), self.right.string())
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 pub struct OrExpr<Lhs: Expr, Rhs: Expr> {
     left: Lhs,
-    right: Rhs
+    right: Rhs,
+    span: Span
 }
 
 impl<Lhs: Expr, Rhs: Expr> OrExpr<Lhs, Rhs> {
-    pub fn new(left: Lhs, right: Rhs) -> OrExpr<Lhs, Rhs> {
-        OrExpr { left, right }
+    pub fn new(left: Lhs, right: Rhs, span: Span) -> OrExpr<Lhs, Rhs> {
+        OrExpr { left, right, span }
     }
 }
 
 impl<Lhs: Expr, Rhs: Expr> Expr for OrExpr<Lhs, Rhs> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let left = (self.left.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        let right = (self.right.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        Rc::new(BoolExpr::new(left.0 || right.0))
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let left = bool(&self.left, bindings)?;
+        let right = bool(&self.right, bindings)?;
+        Ok(Value::Bool(left || right))
     }
     
     fn string(&self) -> String {
         format!("({} OR {})", self.left.string(), self.right.string())
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 pub struct NotExpr<E: Expr> {
-    expr: E
+    expr: E,
+    span: Span
 }
 
 impl<E: Expr> NotExpr<E> {
-    pub fn new(expr: E) -> NotExpr<E> {
-        NotExpr { expr }
+    pub fn new(expr: E, span: Span) -> NotExpr<E> {
+        NotExpr { expr, span }
     }
 }
 
 impl<E: Expr> Expr for NotExpr<E> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let expr = (self.expr.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        Rc::new(BoolExpr::new(!expr.0))
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let expr = bool(&self.expr, bindings)?;
+        Ok(Value::Bool(!expr))
     }
     
     fn string(&self) -> String {
-        format!("NOT {}", self.expr.string())
+        format!("(NOT {})", self.expr.string())
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
     }
 }
 
 pub struct LtExpr<Lhs: Expr, Rhs: Expr> {
     left: Lhs,
-    right: Rhs
+    right: Rhs,
+    span: Span
 }
 
 impl<Lhs: Expr, Rhs: Expr> LtExpr<Lhs, Rhs> {
-    pub fn new(left: Lhs, right: Rhs) -> LtExpr<Lhs, Rhs> {
-        LtExpr { left, right }
+    pub fn new(left: Lhs, right: Rhs, span: Span) -> LtExpr<Lhs, Rhs> {
+        LtExpr { left, right, span }
     }
 }
 
 impl<Lhs: Expr, Rhs: Expr> Expr for LtExpr<Lhs, Rhs> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let left = (self.left.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        let right = (self.right.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        Rc::new(BoolExpr::new(left.0 < right.0))
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let ordering = order(&self.left, &self.right, bindings, &self.span)?;
+        Ok(Value::Bool(ordering == Some(Ordering::Less)))
     }
 
     fn string(&self) -> String {
         format!("({} < {})", self.left.string(), self.right.string())
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 pub struct LeExpr<Lhs: Expr, Rhs: Expr> {
     left: Lhs,
-    right: Rhs
+    right: Rhs,
+    span: Span
 }
 
 impl<Lhs: Expr, Rhs: Expr> LeExpr<Lhs, Rhs> {
-    pub fn new(left: Lhs, right: Rhs) -> LeExpr<Lhs, Rhs> {
-        LeExpr { left, right }
+    pub fn new(left: Lhs, right: Rhs, span: Span) -> LeExpr<Lhs, Rhs> {
+        LeExpr { left, right, span }
     }
 }
 
 impl<Lhs: Expr, Rhs: Expr> Expr for LeExpr<Lhs, Rhs> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let left = (self.left.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        let right = (self.right.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        Rc::new(BoolExpr::new(left.0 <= right.0))
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let ordering = order(&self.left, &self.right, bindings, &self.span)?;
+        Ok(Value::Bool(matches!(ordering, Some(Ordering::Less | Ordering::Equal))))
     }
 
     fn string(&self) -> String {
         format!("({} <= {})", self.left.string(), self.right.string())
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 pub struct EqExpr<Lhs: Expr, Rhs: Expr> {
     left: Lhs,
-    right: Rhs
+    right: Rhs,
+    span: Span
 }
 
 impl<Lhs: Expr, Rhs: Expr> EqExpr<Lhs, Rhs> {
-    pub fn new(left: Lhs, right: Rhs) -> EqExpr<Lhs, Rhs> {
-        EqExpr { left, right }
+    pub fn new(left: Lhs, right: Rhs, span: Span) -> EqExpr<Lhs, Rhs> {
+        EqExpr { left, right, span }
     }
 }
 
 impl<Lhs: Expr, Rhs: Expr> Expr for EqExpr<Lhs, Rhs> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let left = (self.left.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        let right = (self.right.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        Rc::new(BoolExpr::new(left.0 == right.0))
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let left = self.left.value(bindings)?;
+        let right = self.right.value(bindings)?;
+        Ok(Value::Bool(left == right))
     }
 
     fn string(&self) -> String {
         format!("({} == {})", self.left.string(), self.right.string())
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 pub struct GeExpr<Lhs: Expr, Rhs: Expr> {
     left: Lhs,
-    right: Rhs
+    right: Rhs,
+    span: Span
 }
 
 impl<Lhs: Expr, Rhs: Expr> GeExpr<Lhs, Rhs> {
-    pub fn new(left: Lhs, right: Rhs) -> GeExpr<Lhs, Rhs> {
-        GeExpr { left, right }
+    pub fn new(left: Lhs, right: Rhs, span: Span) -> GeExpr<Lhs, Rhs> {
+        GeExpr { left, right, span }
     }
 }
 
 impl<Lhs: Expr, Rhs: Expr> Expr for GeExpr<Lhs, Rhs> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let left = (self.left.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        let right = (self.right.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        Rc::new(BoolExpr::new(left.0 >= right.0))
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let ordering = order(&self.left, &self.right, bindings, &self.span)?;
+        Ok(Value::Bool(matches!(ordering, Some(Ordering::Greater | Ordering::Equal))))
     }
 
     fn string(&self) -> String {
         format!("({} >= {})", self.left.string(), self.right.string())
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 pub struct GtExpr<Lhs: Expr, Rhs: Expr> {
     left: Lhs,
-    right: Rhs
+    right: Rhs,
+    span: Span
 }
 
 impl<Lhs: Expr, Rhs: Expr> GtExpr<Lhs, Rhs> {
-    pub fn new(left: Lhs, right: Rhs) -> GtExpr<Lhs, Rhs> {
-        GtExpr { left, right }
+    pub fn new(left: Lhs, right: Rhs, span: Span) -> GtExpr<Lhs, Rhs> {
+        GtExpr { left, right, span }
     }
 }
 
 impl<Lhs: Expr, Rhs: Expr> Expr for GtExpr<Lhs, Rhs> {
-    fn value(&self, bindings: &mut Bindings) -> Rc<dyn Expr> {
-        let left = (self.left.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        let right = (self.right.value(bindings) as Rc<dyn Any>).downcast::<BoolExpr>().unwrap();
-        Rc::new(BoolExpr::new(left.0 > right.0))
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let ordering = order(&self.left, &self.right, bindings, &self.span)?;
+        Ok(Value::Bool(ordering == Some(Ordering::Greater)))
     }
 
     fn string(&self) -> String {
         format!("({} > {})", self.left.string(), self.right.string())
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+pub struct CondExpr<C: Expr, T: Expr, F: Expr> {
+    cond: C,
+    then: T,
+    otherwise: F,
+    span: Span
+}
+
+impl<C: Expr, T: Expr, F: Expr> CondExpr<C, T, F> {
+    pub fn new(cond: C, then: T, otherwise: F, span: Span) -> CondExpr<C, T, F> {
+        CondExpr { cond, then, otherwise, span }
+    }
+}
+
+impl<C: Expr, T: Expr, F: Expr> Expr for CondExpr<C, T, F> {
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        if condition(&self.cond, bindings)? {
+            self.then.value(bindings)
+        } else {
+            self.otherwise.value(bindings)
+        }
+    }
+
+    fn tail(&self, bindings: &mut Bindings) -> Result<Flow> {
+        if condition(&self.cond, bindings)? {
+            self.then.tail(bindings)
+        } else {
+            self.otherwise.tail(bindings)
+        }
+    }
+
+    fn string(&self) -> String {
+        format!("(IF {} THEN {} ELSE {})", self.cond.string(), self.then.string(), self.otherwise.string())
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::{Program, Overflow};
+    use crate::parser::Parser;
+
+    const MAX: &str = "170141183460469231731687303715884105727";
+    const MIN: &str = "-170141183460469231731687303715884105728";
+
+    // The value of expr under overflow, or the error it raises
+    fn eval(overflow: Overflow, expr: &str) -> Result<String, String> {
+        let mut program = Program::new();
+        program.set_overflow(overflow);
+
+        let expr = expr.replace("MAX", MAX).replace("MIN", &format!("(0 - {MAX} - 1)"));
+        let expr = Parser::new(&expr, "test").unwrap().parse_expr().unwrap();
+        program.eval(&expr).map(|value| value.string()).map_err(|err| err.kind.to_string())
+    }
+
+    fn ok(value: &str) -> Result<String, String> {
+        Ok(value.to_string())
+    }
+
+    #[test]
+    fn promote_grows_past_i128() {
+        assert_eq!(eval(Overflow::Promote, "MAX + 1"), ok("170141183460469231731687303715884105728"));
+        assert_eq!(eval(Overflow::Promote, "MIN - 1"), ok("-170141183460469231731687303715884105729"));
+        assert_eq!(eval(Overflow::Promote, "MIN / -1"), ok("170141183460469231731687303715884105728"));
+        // Back to a plain int once it fits again
+        assert_eq!(eval(Overflow::Promote, "MAX + 1 - 1"), ok(MAX));
+    }
+
+    #[test]
+    fn checked_fails() {
+        assert_eq!(eval(Overflow::Checked, "MAX + 1"), Err("INTEGER OVERFLOW IN '+'".to_string()));
+        assert_eq!(eval(Overflow::Checked, "MIN - 1"), Err("INTEGER OVERFLOW IN '-'".to_string()));
+        assert_eq!(eval(Overflow::Checked, "MAX * 2"), Err("INTEGER OVERFLOW IN '*'".to_string()));
+        assert_eq!(eval(Overflow::Checked, "MIN / -1"), Err("INTEGER OVERFLOW IN '/'".to_string()));
+        assert_eq!(eval(Overflow::Checked, "MAX - 1"), ok("170141183460469231731687303715884105726"));
+    }
+
+    #[test]
+    fn wrapping_goes_around() {
+        assert_eq!(eval(Overflow::Wrapping, "MAX + 1"), ok(MIN));
+        assert_eq!(eval(Overflow::Wrapping, "MIN - 1"), ok(MAX));
+        assert_eq!(eval(Overflow::Wrapping, "MAX * 2"), ok("-2"));
+        assert_eq!(eval(Overflow::Wrapping, "MIN / -1"), ok(MIN));
+    }
+
+    #[test]
+    fn saturating_clamps() {
+        assert_eq!(eval(Overflow::Saturating, "MAX + 1"), ok(MAX));
+        assert_eq!(eval(Overflow::Saturating, "MIN - 1"), ok(MIN));
+        assert_eq!(eval(Overflow::Saturating, "MIN * 2"), ok(MIN));
+        assert_eq!(eval(Overflow::Saturating, "MIN / -1"), ok(MAX));
+    }
+
+    #[test]
+    fn remainders_never_overflow() {
+        for overflow in [Overflow::Promote, Overflow::Checked, Overflow::Wrapping, Overflow::Saturating] {
+            assert_eq!(eval(overflow, "MIN % -1"), ok("0"));
+            assert_eq!(eval(overflow, "-7 % 2"), ok("-1"));
+        }
+    }
+
+    #[test]
+    fn division_by_zero_fails_in_every_mode() {
+        for overflow in [Overflow::Promote, Overflow::Checked, Overflow::Wrapping, Overflow::Saturating] {
+            assert_eq!(eval(overflow, "1 / 0"), Err("DIVISION BY ZERO".to_string()));
+            assert_eq!(eval(overflow, "1 % 0"), Err("DIVISION BY ZERO".to_string()));
+            assert_eq!(eval(overflow, "MIN % 0"), Err("DIVISION BY ZERO".to_string()));
+        }
+    }
+
+    #[test]
+    fn ints_and_floats_compare_exactly() {
+        let run = |expr| eval(Overflow::Promote, expr);
+
+        assert_eq!(run("9007199254740993 == 9007199254740992.0"), ok("FALSE"));
+        assert_eq!(run("9007199254740993 > 9007199254740992.0"), ok("TRUE"));
+        assert_eq!(run("9007199254740992.0 < 9007199254740993"), ok("TRUE"));
+        assert_eq!(run("MAX + 1 == 1.7014118346046923e38"), ok("TRUE"));
+        assert_eq!(run("MAX == 1.7014118346046923e38"), ok("FALSE"));
+        assert_eq!(run("2 < 2.5"), ok("TRUE"));
+        assert_eq!(run("-2 > -2.5"), ok("TRUE"));
+    }
 }
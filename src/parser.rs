@@ -0,0 +1,1088 @@
+use crate::core::{Expr, Cell, Stmt, Block, Definition};
+use crate::expressions::*;
+use crate::statements::*;
+use crate::records::*;
+use crate::unions::*;
+use crate::operations::*;
+use crate::bigint::BigInt;
+use crate::lexer::{Token, Lexeme, tokenize};
+use crate::span::{Span, Sources};
+use std::rc::Rc;
+use std::fmt;
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    pub eof: bool
+}
+
+impl ParseError {
+    pub fn new(message: String, span: Span) -> ParseError {
+        ParseError { message, span, eof: false }
+    }
+
+    pub fn eof(message: &str, span: Span) -> ParseError {
+        ParseError { message: message.to_string(), span, eof: true }
+    }
+
+    pub fn render(&self, sources: &Sources) -> String {
+        sources.render("SYNTAX ERROR", &self.message, Some(&self.span))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+enum Parsed {
+    Cell(Rc<dyn Cell>),
+    Expr(Rc<dyn Expr>)
+}
+
+impl Parsed {
+    fn expr(self) -> Rc<dyn Expr> {
+        match self {
+            Parsed::Cell(cell) => cell,
+            Parsed::Expr(expr) => expr
+        }
+    }
+}
+
+// The variants a MATCH names and whether it has an ELSE arm, checked once the whole
+// program is parsed and every union in it is known
+struct MatchArms {
+    variants: Vec<String>,
+    otherwise: bool,
+    span: Span
+}
+
+// The positional and named arguments of a call
+type Args = (Vec<Arg>, Vec<NamedArg>);
+
+pub struct Parser {
+    lexemes: Vec<Lexeme>,
+    pos: usize,
+    // Labels of the loops around the statement being parsed, innermost last
+    loops: Vec<Option<String>>,
+    matches: Vec<MatchArms>
+}
+
+impl Parser {
+    pub fn new(source: &str, file: &str) -> Result<Parser, ParseError> {
+        Ok(Parser { lexemes: tokenize(source, file)?, pos: 0, loops: Vec::new(), matches: Vec::new() })
+    }
+
+    fn peek(&self) -> &Token {
+        &self.lexemes[self.pos].token
+    }
+
+    fn peek_at(&self, offset: usize) -> &Token {
+        let pos = (self.pos + offset).min(self.lexemes.len() - 1);
+        &self.lexemes[pos].token
+    }
+
+    fn span(&self) -> Span {
+        self.lexemes[self.pos].span.clone()
+    }
+
+    fn next(&mut self) -> Lexeme {
+        let lexeme = self.lexemes[self.pos].clone();
+        if self.pos < self.lexemes.len() - 1 {
+            self.pos += 1;
+        }
+        lexeme
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn unexpected(&self, expected: &str) -> ParseError {
+        let lexeme = &self.lexemes[self.pos];
+        let message = format!("EXPECTED {expected}, FOUND {}", lexeme.token);
+
+        if lexeme.token == Token::Eof {
+            ParseError::eof(&message, lexeme.span.clone())
+        } else {
+            ParseError::new(message, lexeme.span.clone())
+        }
+    }
+
+    fn error<T>(&self, expected: &str) -> Result<T, ParseError> {
+        Err(self.unexpected(expected))
+    }
+
+    fn expect(&mut self, token: Token) -> Result<Lexeme, ParseError> {
+        if *self.peek() == token {
+            Ok(self.next())
+        } else {
+            self.error(&token.to_string())
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, ParseError> {
+        match self.peek().clone() {
+            Token::Ident(name) => {
+                self.next();
+                Ok(name)
+            }
+            _ => self.error("IDENTIFIER")
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.eat(&Token::Newline) {}
+    }
+
+    fn end_of_statement(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            Token::Newline => {
+                self.skip_newlines();
+                Ok(())
+            }
+            Token::RBrace | Token::Eof => Ok(()),
+            _ => self.error("END OF STATEMENT")
+        }
+    }
+
+    pub fn at_end(&mut self) -> bool {
+        self.skip_newlines();
+        *self.peek() == Token::Eof
+    }
+
+    pub fn at_definition(&mut self) -> bool {
+        self.skip_newlines();
+        matches!(self.peek(), Token::Define | Token::Record | Token::Union)
+    }
+
+    pub fn parse_program(&mut self) -> Result<Vec<Definition>, ParseError> {
+        let mut defs = Vec::new();
+
+        while !self.at_end() {
+            defs.push(self.parse_definition()?);
+            self.end_of_statement()?;
+        }
+
+        self.check_matches(&defs)?;
+
+        Ok(defs)
+    }
+
+    // Unions declared elsewhere, as in earlier REPL inputs, are left to the runtime check
+    fn check_matches(&mut self, defs: &[Definition]) -> Result<(), ParseError> {
+        let unions: Vec<&Union> = defs.iter()
+            .filter_map(|def| match def {
+                Definition::Union(union, _) => Some(&**union),
+                _ => None
+            })
+            .collect();
+
+        for arms in self.matches.drain(..) {
+            let Some(union) = unions.iter().find(|union| union.variants().contains(&arms.variants[0])) else {
+                continue;
+            };
+
+            if let Some(stray) = arms.variants.iter().find(|variant| !union.variants().contains(variant)) {
+                return Err(ParseError::new(format!("{stray} IS NOT A VARIANT OF {}", union.name()), arms.span));
+            }
+
+            if arms.otherwise {
+                continue;
+            }
+
+            if let Some(missing) = union.variants().iter().find(|variant| !arms.variants.contains(variant)) {
+                return Err(ParseError::new(format!("MATCH ON {} MISSES {missing}", union.name()), arms.span));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn parse_definition(&mut self) -> Result<Definition, ParseError> {
+        if *self.peek() == Token::Record {
+            return self.parse_record();
+        }
+
+        if *self.peek() == Token::Union {
+            return self.parse_union();
+        }
+
+        self.expect(Token::Define)?;
+        let span = self.span();
+        let name = self.ident()?;
+        self.expect(Token::Assign)?;
+        let expr = self.parse_bound(&name)?;
+
+        Ok(Definition::new(AddVarStmt::new(VarExpr::new(name, span), expr)))
+    }
+
+    fn parse_record(&mut self) -> Result<Definition, ParseError> {
+        let span = self.expect(Token::Record)?.span;
+        let name = self.ident()?;
+        let fields = self.parse_fields()?;
+
+        Ok(Definition::record(RecordType::new(name, fields, span)))
+    }
+
+    fn parse_fields(&mut self) -> Result<Vec<String>, ParseError> {
+        self.expect(Token::LBracket)?;
+
+        let mut fields: Vec<String> = Vec::new();
+        while *self.peek() != Token::RBracket {
+            let field_span = self.span();
+            let field = self.ident()?;
+            if fields.contains(&field) {
+                return Err(ParseError::new(format!("FIELD {field} DECLARED TWICE"), field_span));
+            }
+            fields.push(field);
+            if !self.eat(&Token::Comma) { break; }
+        }
+
+        self.expect(Token::RBracket)?;
+
+        Ok(fields)
+    }
+
+    // A variant without brackets has no fields
+    fn parse_union(&mut self) -> Result<Definition, ParseError> {
+        let span = self.expect(Token::Union)?.span;
+        let name = self.ident()?;
+        self.expect(Token::LBracket)?;
+
+        let mut variants: Vec<String> = Vec::new();
+        let mut fields = Vec::new();
+        while *self.peek() != Token::RBracket {
+            let variant_span = self.span();
+            let variant = self.ident()?;
+            if variants.contains(&variant) {
+                return Err(ParseError::new(format!("VARIANT {variant} DECLARED TWICE"), variant_span));
+            }
+            variants.push(variant);
+            fields.push(if *self.peek() == Token::LBracket { self.parse_fields()? } else { Vec::new() });
+            if !self.eat(&Token::Comma) { break; }
+        }
+
+        let end = self.expect(Token::RBracket)?.span;
+        if variants.is_empty() {
+            return Err(ParseError::new(format!("UNION {name} HAS NO VARIANTS"), span.to(&end)));
+        }
+
+        Ok(Definition::union(Union::new(name, variants, span), fields))
+    }
+
+    fn parse_bound(&mut self, name: &str) -> Result<Rc<dyn Expr>, ParseError> {
+        if *self.peek() == Token::Function {
+            return self.parse_function(name);
+        }
+
+        self.parse_expr()
+    }
+
+    fn parse_function(&mut self, name: &str) -> Result<Rc<dyn Expr>, ParseError> {
+        let span = self.expect(Token::Function)?.span;
+        self.expect(Token::LBracket)?;
+
+        // Once one argument has a default, every argument after it needs one too.
+        // A ...rest argument has to be the last one
+        let mut args: Vec<String> = Vec::new();
+        let mut defaults = Vec::new();
+        let mut rest = None;
+        while *self.peek() != Token::RBracket {
+            let arg_span = self.span();
+            let spread = self.eat(&Token::Ellipsis);
+            let arg = self.ident()?;
+            if args.contains(&arg) {
+                return Err(ParseError::new(format!("ARGUMENT {arg} DECLARED TWICE"), arg_span));
+            }
+
+            if spread {
+                self.eat(&Token::Comma);
+                if *self.peek() != Token::RBracket {
+                    return Err(ParseError::new(format!("ARGUMENT ...{arg} MUST COME LAST"), arg_span));
+                }
+                rest = Some(arg);
+                break;
+            }
+
+            if self.eat(&Token::Assign) {
+                defaults.push(self.parse_expr()?);
+            } else if !defaults.is_empty() {
+                return Err(ParseError::new(format!("ARGUMENT {arg} NEEDS A DEFAULT"), arg_span));
+            }
+
+            args.push(arg);
+            if !self.eat(&Token::Comma) { break; }
+        }
+
+        self.expect(Token::RBracket)?;
+
+        // BREAK and CONTINUE cannot reach loops outside the function
+        let loops = std::mem::take(&mut self.loops);
+        let body = self.parse_block();
+        self.loops = loops;
+        let body = body?;
+
+        Ok(Rc::new(Function::new(name.to_string(), args, body, span).defaults(defaults).rest(rest)))
+    }
+
+    fn parse_block(&mut self) -> Result<Block, ParseError> {
+        let span = self.expect(Token::LBrace)?.span;
+        self.skip_newlines();
+
+        let mut statements = Vec::new();
+        while *self.peek() != Token::RBrace {
+            statements.push(self.parse_stmt()?);
+            self.end_of_statement()?;
+        }
+
+        self.expect(Token::RBrace)?;
+
+        Ok(Block::new(statements, span))
+    }
+
+    fn parse_if(&mut self) -> Result<Rc<dyn Stmt>, ParseError> {
+        let span = self.expect(Token::If)?.span;
+        let cond = self.parse_expr()?;
+        let then = self.parse_block()?;
+
+        // ELSE may start the line after the closing brace
+        if *self.peek() == Token::Newline && *self.peek_at(1) == Token::Else {
+            self.next();
+        }
+
+        if !self.eat(&Token::Else) {
+            return Ok(Rc::new(IfStmt::new(cond, then, None, span)));
+        }
+
+        let otherwise: Rc<dyn Stmt> = if *self.peek() == Token::If {
+            self.parse_if()?
+        } else {
+            Rc::new(self.parse_block()?)
+        };
+
+        Ok(Rc::new(IfStmt::new(cond, then, Some(otherwise), span)))
+    }
+
+    fn parse_loop_body(&mut self, label: &Option<String>) -> Result<Block, ParseError> {
+        self.loops.push(label.clone());
+        let body = self.parse_block();
+        self.loops.pop();
+
+        body
+    }
+
+    fn parse_loop(&mut self, label: Option<String>) -> Result<Rc<dyn Stmt>, ParseError> {
+        let span = self.span();
+
+        if self.eat(&Token::While) {
+            let cond = self.parse_expr()?;
+            let body = self.parse_loop_body(&label)?;
+
+            let stmt = WhileStmt::new(cond, body, span);
+            return Ok(match &label {
+                Some(label) => Rc::new(stmt.labelled(label)),
+                None => Rc::new(stmt)
+            });
+        }
+
+        self.expect(Token::For)?;
+        let var_span = self.span();
+        let var = VarExpr::new(self.ident()?, var_span);
+        self.expect(Token::Assign)?;
+        let start = self.parse_expr()?;
+        self.expect(Token::To)?;
+        let end = self.parse_expr()?;
+        let step = if self.eat(&Token::Step) { Some(self.parse_expr()?) } else { None };
+        let body = self.parse_loop_body(&label)?;
+
+        let mut stmt = ForStmt::new(var, start, end, body, span);
+        if let Some(step) = step {
+            stmt = stmt.step(step);
+        }
+        if let Some(label) = &label {
+            stmt = stmt.labelled(label);
+        }
+
+        Ok(Rc::new(stmt))
+    }
+
+    fn parse_jump(&mut self) -> Result<Rc<dyn Stmt>, ParseError> {
+        let lexeme = self.next();
+        let label = match self.peek().clone() {
+            Token::Ident(label) => {
+                self.next();
+                Some(label)
+            }
+            _ => None
+        };
+
+        let keyword = lexeme.token.to_string();
+        match &label {
+            Some(label) if !self.loops.contains(&Some(label.clone())) => {
+                return Err(ParseError::new(format!("NO LOOP LABELLED {label} AROUND {keyword}"), lexeme.span));
+            }
+            None if self.loops.is_empty() => {
+                return Err(ParseError::new(format!("{keyword} OUTSIDE OF A LOOP"), lexeme.span));
+            }
+            _ => {}
+        }
+
+        Ok(match lexeme.token {
+            Token::Break => Rc::new(BreakStmt::new(label, lexeme.span)),
+            _ => Rc::new(ContinueStmt::new(label, lexeme.span))
+        })
+    }
+
+    fn parse_return(&mut self) -> Result<Rc<dyn Stmt>, ParseError> {
+        let span = self.expect(Token::Return)?.span;
+
+        let expr = match self.peek() {
+            Token::Newline | Token::RBrace | Token::Eof => None,
+            _ => Some(self.parse_expr()?)
+        };
+
+        Ok(Rc::new(ReturnStmt::new(expr, span)))
+    }
+
+    pub fn parse_stmt(&mut self) -> Result<Rc<dyn Stmt>, ParseError> {
+        match self.peek().clone() {
+            Token::LBrace => return Ok(Rc::new(self.parse_block()?)),
+            Token::If => return self.parse_if(),
+            Token::While | Token::For => return self.parse_loop(None),
+            Token::Break | Token::Continue => return self.parse_jump(),
+            Token::Return => return self.parse_return(),
+            Token::Match => return self.parse_match_stmt(),
+            Token::Ident(label) if *self.peek_at(1) == Token::Colon => {
+                self.next();
+                self.next();
+                if !matches!(self.peek(), Token::While | Token::For) {
+                    return self.error("LOOP AFTER LABEL");
+                }
+                return self.parse_loop(Some(label));
+            }
+            _ => {}
+        }
+
+        if self.destructuring() {
+            return self.parse_destructuring();
+        }
+
+        if let (Token::Ident(name), Token::Assign) = (self.peek().clone(), self.peek_at(1)) {
+            let span = self.next().span;
+            self.next();
+            let expr = self.parse_bound(&name)?;
+            return Ok(Rc::new(AddVarStmt::new(VarExpr::new(name, span), expr)));
+        }
+
+        let parsed = self.parse_or()?;
+
+        if *self.peek() != Token::Change {
+            return Ok(Rc::new(EvalStmt::new(parsed.expr())));
+        }
+
+        let span = self.next().span;
+        let cell = match parsed {
+            Parsed::Cell(cell) => cell,
+            Parsed::Expr(expr) => {
+                return Err(ParseError::new("LEFT SIDE OF '=' IS NOT ASSIGNABLE".to_string(), expr.span().clone()));
+            }
+        };
+        let expr = self.parse_expr()?;
+
+        Ok(Rc::new(ChangeStmt::new(cell, expr, span)))
+    }
+
+    // MATCH subject { ... } up to the first arm, the arms are read by the caller
+    fn parse_match_head(&mut self) -> Result<(Span, Rc<dyn Expr>), ParseError> {
+        let span = self.expect(Token::Match)?.span;
+        let subject = self.parse_expr()?;
+        self.expect(Token::LBrace)?;
+        self.skip_newlines();
+
+        Ok((span, subject))
+    }
+
+    // Arms are separated by newlines or commas, returns false once the closing brace is next
+    fn next_arm(&mut self) -> bool {
+        self.eat(&Token::Comma);
+        self.skip_newlines();
+        *self.peek() != Token::RBrace
+    }
+
+    fn parse_pattern(&mut self, variants: &mut Vec<String>) -> Result<Pattern, ParseError> {
+        let span = self.span();
+        let variant = self.ident()?;
+        if variants.contains(&variant) {
+            return Err(ParseError::new(format!("ARM {variant} GIVEN TWICE"), span));
+        }
+        variants.push(variant.clone());
+
+        if !self.eat(&Token::LBracket) {
+            return Ok(Pattern::new(variant, None, span));
+        }
+
+        let mut names: Vec<String> = Vec::new();
+        while *self.peek() != Token::RBracket {
+            let name_span = self.span();
+            let name = self.ident()?;
+            if name != "_" && names.contains(&name) {
+                return Err(ParseError::new(format!("VARIABLE {name} BOUND TWICE"), name_span));
+            }
+            names.push(name);
+            if !self.eat(&Token::Comma) { break; }
+        }
+
+        let end = self.expect(Token::RBracket)?.span;
+        Ok(Pattern::new(variant, Some(names), span.to(&end)))
+    }
+
+    // Records the arms for check_matches, a MATCH with no arms at all is rejected here
+    fn finish_match(&mut self, span: &Span, variants: Vec<String>, otherwise: bool) -> Result<(), ParseError> {
+        let end = self.expect(Token::RBrace)?.span;
+
+        if variants.is_empty() {
+            if !otherwise {
+                return Err(ParseError::new("MATCH HAS NO ARMS".to_string(), span.to(&end)));
+            }
+            return Ok(());
+        }
+
+        self.matches.push(MatchArms { variants, otherwise, span: span.clone() });
+        Ok(())
+    }
+
+    fn parse_match_stmt(&mut self) -> Result<Rc<dyn Stmt>, ParseError> {
+        let (span, subject) = self.parse_match_head()?;
+
+        let mut variants = Vec::new();
+        let mut arms = Vec::new();
+        let mut otherwise = None;
+        while *self.peek() != Token::RBrace {
+            if self.eat(&Token::Else) {
+                otherwise = Some(self.parse_block()?);
+                self.eat(&Token::Comma);
+                self.skip_newlines();
+                break;
+            }
+
+            let pattern = self.parse_pattern(&mut variants)?;
+            arms.push((pattern, self.parse_block()?));
+            if !self.next_arm() { break; }
+        }
+
+        self.finish_match(&span, variants, otherwise.is_some())?;
+        Ok(Rc::new(MatchStmt::new(subject, arms, otherwise, span)))
+    }
+
+    fn parse_match_expr(&mut self) -> Result<Parsed, ParseError> {
+        let (span, subject) = self.parse_match_head()?;
+
+        let mut variants = Vec::new();
+        let mut arms = Vec::new();
+        let mut otherwise = None;
+        while *self.peek() != Token::RBrace {
+            if self.eat(&Token::Else) {
+                otherwise = Some(self.parse_expr()?);
+                self.eat(&Token::Comma);
+                self.skip_newlines();
+                break;
+            }
+
+            let pattern = self.parse_pattern(&mut variants)?;
+            self.expect(Token::Then)?;
+            arms.push((pattern, self.parse_expr()?));
+            if !self.next_arm() { break; }
+        }
+
+        self.finish_match(&span, variants, otherwise.is_some())?;
+        Ok(Parsed::Expr(Rc::new(MatchExpr::new(subject, arms, otherwise, span))))
+    }
+
+    // Looks ahead for (a, b, ...) := without consuming anything
+    fn destructuring(&self) -> bool {
+        if *self.peek() != Token::LParen {
+            return false;
+        }
+
+        let mut offset = 1;
+        loop {
+            if !matches!(self.peek_at(offset), Token::Ident(_)) {
+                return false;
+            }
+
+            match self.peek_at(offset + 1) {
+                Token::Comma => offset += 2,
+                Token::RParen => return offset > 1 && *self.peek_at(offset + 2) == Token::Assign,
+                _ => return false
+            }
+        }
+    }
+
+    fn parse_destructuring(&mut self) -> Result<Rc<dyn Stmt>, ParseError> {
+        let span = self.expect(Token::LParen)?.span;
+
+        let mut vars: Vec<VarExpr> = Vec::new();
+        while *self.peek() != Token::RParen {
+            let var_span = self.span();
+            let name = self.ident()?;
+            if vars.iter().any(|var| var.string() == name) {
+                return Err(ParseError::new(format!("VARIABLE {name} BOUND TWICE"), var_span));
+            }
+            vars.push(VarExpr::new(name, var_span));
+            if !self.eat(&Token::Comma) { break; }
+        }
+
+        self.expect(Token::RParen)?;
+        self.expect(Token::Assign)?;
+        let expr = self.parse_expr()?;
+
+        Ok(Rc::new(AddVarsStmt::new(vars, expr, span)))
+    }
+
+    pub fn parse_expr(&mut self) -> Result<Rc<dyn Expr>, ParseError> {
+        Ok(self.parse_or()?.expr())
+    }
+
+    fn parse_or(&mut self) -> Result<Parsed, ParseError> {
+        let mut left = self.parse_and()?;
+
+        while *self.peek() == Token::Or {
+            let span = self.next().span;
+            let right = self.parse_and()?.expr();
+            left = Parsed::Expr(Rc::new(OrExpr::new(left.expr(), right, span)));
+        }
+
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Parsed, ParseError> {
+        let mut left = self.parse_not()?;
+
+        while *self.peek() == Token::And {
+            let span = self.next().span;
+            let right = self.parse_not()?.expr();
+            left = Parsed::Expr(Rc::new(AndExpr::new(left.expr(), right, span)));
+        }
+
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Parsed, ParseError> {
+        if *self.peek() == Token::Not {
+            let span = self.next().span;
+            let expr = self.parse_not()?.expr();
+            return Ok(Parsed::Expr(Rc::new(NotExpr::new(expr, span))));
+        }
+
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Parsed, ParseError> {
+        let left = self.parse_sum()?;
+
+        let op = self.peek().clone();
+        if !matches!(op, Token::Eq | Token::Lt | Token::Le | Token::Ge | Token::Gt) {
+            return Ok(left);
+        }
+
+        let span = self.next().span;
+        let (left, right) = (left.expr(), self.parse_sum()?.expr());
+
+        let expr: Rc<dyn Expr> = match op {
+            Token::Eq => Rc::new(EqExpr::new(left, right, span)),
+            Token::Lt => Rc::new(LtExpr::new(left, right, span)),
+            Token::Le => Rc::new(LeExpr::new(left, right, span)),
+            Token::Ge => Rc::new(GeExpr::new(left, right, span)),
+            _ => Rc::new(GtExpr::new(left, right, span))
+        };
+
+        Ok(Parsed::Expr(expr))
+    }
+
+    fn parse_sum(&mut self) -> Result<Parsed, ParseError> {
+        let mut left = self.parse_term()?;
+
+        loop {
+            let op = self.peek().clone();
+            if !matches!(op, Token::Plus | Token::Minus) { break; }
+
+            let span = self.next().span;
+            let right = self.parse_term()?.expr();
+
+            left = Parsed::Expr(match op {
+                Token::Plus => Rc::new(AddExpr::new(left.expr(), right, span)),
+                _ => Rc::new(SubExpr::new(left.expr(), right, span))
+            });
+        }
+
+        Ok(left)
+    }
+
+    fn parse_term(&mut self) -> Result<Parsed, ParseError> {
+        let mut left = self.parse_unary()?;
+
+        loop {
+            let op = self.peek().clone();
+            if !matches!(op, Token::Star | Token::Slash | Token::Percent) { break; }
+
+            let span = self.next().span;
+            let right = self.parse_unary()?.expr();
+
+            left = Parsed::Expr(match op {
+                Token::Star => Rc::new(MulExpr::new(left.expr(), right, span)),
+                Token::Slash => Rc::new(DivExpr::new(left.expr(), right, span)),
+                _ => Rc::new(ModExpr::new(left.expr(), right, span))
+            });
+        }
+
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Parsed, ParseError> {
+        let lexeme = self.lexemes[self.pos].clone();
+
+        match lexeme.token {
+            Token::Minus => {
+                self.next();
+
+                if let Token::Int(digits) = self.peek().clone() {
+                    let span = lexeme.span.to(&self.next().span);
+                    return Ok(Parsed::Expr(self.int(&format!("-{digits}"), span)));
+                }
+
+                if let Token::Float(digits) = self.peek().clone() {
+                    let span = lexeme.span.to(&self.next().span);
+                    return Ok(Parsed::Expr(self.float(&format!("-{digits}"), span)?));
+                }
+
+                let expr = self.parse_unary()?.expr();
+                let zero = IntExpr::new(0, lexeme.span.clone());
+                Ok(Parsed::Expr(Rc::new(SubExpr::new(zero, expr, lexeme.span))))
+            }
+            Token::Amp => {
+                self.next();
+
+                match self.parse_unary()? {
+                    Parsed::Cell(cell) => Ok(Parsed::Expr(Rc::new(RefExpr::new(cell, lexeme.span)))),
+                    Parsed::Expr(_) => Err(ParseError::new("CANNOT TAKE A REFERENCE TO A VALUE".to_string(), lexeme.span))
+                }
+            }
+            Token::Star => {
+                self.next();
+                let expr = self.parse_unary()?.expr();
+                Ok(Parsed::Cell(Rc::new(DerefExpr::new(expr, lexeme.span))))
+            }
+            _ => self.parse_postfix()
+        }
+    }
+
+    fn parse_postfix(&mut self) -> Result<Parsed, ParseError> {
+        let start = self.span();
+        let mut expr = self.parse_primary()?;
+
+        loop {
+            if self.eat(&Token::LParen) {
+                let (args, named) = self.parse_args()?;
+                let end = self.expect(Token::RParen)?.span;
+                expr = Parsed::Expr(Rc::new(CallExpr::new(expr.expr(), args, start.to(&end)).named(named)));
+            } else if self.eat(&Token::Dot) {
+                let end = self.span();
+                let field = self.ident()?;
+                expr = Parsed::Cell(Rc::new(FieldExpr::new(expr.expr(), field, start.to(&end))));
+            } else if self.eat(&Token::LBracket) {
+                let index = self.parse_expr()?;
+                let end = self.expect(Token::RBracket)?.span;
+                expr = Parsed::Cell(Rc::new(IndexExpr::new(expr.expr(), index, start.to(&end))));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    // Like parse_items, except that name: expr passes an argument by name, after which
+    // every argument has to be named
+    fn parse_args(&mut self) -> Result<Args, ParseError> {
+        let mut args = Vec::new();
+        let mut named: Vec<NamedArg> = Vec::new();
+        while *self.peek() != Token::RParen {
+            let arg_span = self.span();
+            if matches!(self.peek(), Token::Ident(_)) && *self.peek_at(1) == Token::Colon {
+                let arg = self.ident()?;
+                if named.iter().any(|(name, _)| *name == arg) {
+                    return Err(ParseError::new(format!("ARGUMENT {arg} GIVEN TWICE"), arg_span));
+                }
+                self.expect(Token::Colon)?;
+                named.push((arg, self.parse_expr()?));
+            } else if named.is_empty() {
+                let arg = if self.eat(&Token::Ellipsis) { Arg::Spread } else { Arg::One };
+                args.push(arg(self.parse_expr()?));
+            } else {
+                return Err(ParseError::new("POSITIONAL ARGUMENT AFTER A NAMED ONE".to_string(), arg_span));
+            }
+            if !self.eat(&Token::Comma) { break; }
+        }
+
+        Ok((args, named))
+    }
+
+    // Comma separated expressions up to the closing token, which is left for the caller
+    fn parse_items(&mut self, close: Token) -> Result<Vec<Rc<dyn Expr>>, ParseError> {
+        let mut items = Vec::new();
+        while *self.peek() != close {
+            items.push(self.parse_expr()?);
+            if !self.eat(&Token::Comma) { break; }
+        }
+
+        Ok(items)
+    }
+
+    fn parse_primary(&mut self) -> Result<Parsed, ParseError> {
+        let lexeme = self.lexemes[self.pos].clone();
+
+        let span = lexeme.span.clone();
+        let expr: Rc<dyn Expr> = match &lexeme.token {
+            Token::Int(digits) => self.int(digits, span),
+            Token::Float(digits) => self.float(digits, span)?,
+            Token::Text(s) => Rc::new(TextExpr::new(s.clone(), span)),
+            Token::True => Rc::new(BoolExpr::new(true, span)),
+            Token::False => Rc::new(BoolExpr::new(false, span)),
+            Token::Unit => Rc::new(UnitExpr::new(span)),
+            Token::Ident(name) => {
+                self.next();
+                return Ok(Parsed::Cell(Rc::new(VarExpr::new(name.clone(), span))));
+            }
+            Token::LBracket => {
+                self.next();
+                let items = self.parse_items(Token::RBracket)?;
+                let end = self.expect(Token::RBracket)?.span;
+                return Ok(Parsed::Expr(Rc::new(ListExpr::new(items, span.to(&end)))));
+            }
+            Token::LBrace => {
+                self.next();
+                return self.parse_map(span);
+            }
+            Token::New => {
+                self.next();
+                return self.parse_new(span);
+            }
+            Token::Match => return self.parse_match_expr(),
+            // A comma after the first expression makes a tuple instead of a bracketed expression
+            Token::LParen => {
+                self.next();
+                let parsed = self.parse_or()?;
+                if !self.eat(&Token::Comma) {
+                    self.expect(Token::RParen)?;
+                    return Ok(parsed);
+                }
+
+                let mut items = vec![parsed.expr()];
+                items.extend(self.parse_items(Token::RParen)?);
+                let end = self.expect(Token::RParen)?.span;
+                return Ok(Parsed::Expr(Rc::new(TupleExpr::new(items, span.to(&end)))));
+            }
+            Token::If => {
+                self.next();
+                let cond = self.parse_expr()?;
+                self.expect(Token::Then)?;
+                let then = self.parse_expr()?;
+                self.expect(Token::Else)?;
+                let otherwise = self.parse_expr()?;
+                return Ok(Parsed::Expr(Rc::new(CondExpr::new(cond, then, otherwise, span))));
+            }
+            // Only a function bound with := gets a name of its own
+            Token::Function => return Ok(Parsed::Expr(self.parse_function("LAMBDA")?)),
+            _ => return self.error("EXPRESSION")
+        };
+
+        self.next();
+
+        Ok(Parsed::Expr(expr))
+    }
+
+    // Braces keep their newlines for blocks, so a map literal skips them itself
+    fn parse_map(&mut self, span: Span) -> Result<Parsed, ParseError> {
+        let mut entries = Vec::new();
+
+        self.skip_newlines();
+        while *self.peek() != Token::RBrace {
+            let key = self.parse_expr()?;
+            self.expect(Token::Colon)?;
+            let value = self.parse_expr()?;
+            entries.push((key, value));
+
+            self.skip_newlines();
+            if !self.eat(&Token::Comma) { break; }
+            self.skip_newlines();
+        }
+
+        let end = self.expect(Token::RBrace)?.span;
+        Ok(Parsed::Expr(Rc::new(MapExpr::new(entries, span.to(&end)))))
+    }
+
+    fn parse_new(&mut self, span: Span) -> Result<Parsed, ParseError> {
+        let kind_span = self.span();
+        let kind = VarExpr::new(self.ident()?, kind_span);
+        self.expect(Token::LBrace)?;
+
+        let mut fields: Vec<(String, Rc<dyn Expr>)> = Vec::new();
+
+        self.skip_newlines();
+        while *self.peek() != Token::RBrace {
+            let field_span = self.span();
+            let field = self.ident()?;
+            if fields.iter().any(|(name, _)| *name == field) {
+                return Err(ParseError::new(format!("FIELD {field} GIVEN TWICE"), field_span));
+            }
+            self.expect(Token::Colon)?;
+            fields.push((field, self.parse_expr()?));
+
+            self.skip_newlines();
+            if !self.eat(&Token::Comma) { break; }
+            self.skip_newlines();
+        }
+
+        let end = self.expect(Token::RBrace)?.span;
+        Ok(Parsed::Expr(Rc::new(NewExpr::new(kind, fields, span.to(&end)))))
+    }
+
+    // The lexer only hands over digits, so a failed parse means the literal is too large for an i128
+    fn int(&self, digits: &str, span: Span) -> Rc<dyn Expr> {
+        match digits.parse() {
+            Ok(n) => Rc::new(IntExpr::new(n, span)),
+            Err(_) => Rc::new(BigIntExpr::new(BigInt::parse(digits).unwrap(), span))
+        }
+    }
+
+    // An overflowing literal would be INF, which dumps as a name
+    fn float(&self, digits: &str, span: Span) -> Result<Rc<dyn Expr>, ParseError> {
+        let n: f64 = digits.parse().unwrap();
+        if n.is_infinite() {
+            return Err(ParseError::new(format!("{digits} IS TOO LARGE FOR A FLOAT"), span));
+        }
+        Ok(Rc::new(FloatExpr::new(n, span)))
+    }
+}
+
+pub fn parse(source: &str, file: &str) -> Result<Vec<Definition>, ParseError> {
+    Parser::new(source, file)?.parse_program()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump(source: &str) -> String {
+        let definitions = parse(source, "test").unwrap();
+        definitions.iter().map(|def| def.string() + "\n").collect()
+    }
+
+    fn error(source: &str) -> (String, usize, usize) {
+        let err = parse(source, "test").err().unwrap();
+        (err.message, err.span.line, err.span.col)
+    }
+
+    const SOURCE: &str = r#"
+RECORD Point[x, y]
+
+UNION Shape[Circle[radius], Rect[w, h], Empty]
+
+DEFINE limit := 2 * 3 - 1
+
+DEFINE area := FUNCTION[s, scale := 1, ...rest] {
+    RETURN MATCH s {
+        Circle[r] THEN 3 * r * r * scale,
+        Rect[_, h] THEN h,
+        ELSE 0
+    }
+}
+
+DEFINE main := FUNCTION[] {
+    xs := [1, -2.5, "a\n\"b\"", TRUE, UNIT, (1, 2), {1: "one", "k": [3]}]
+    p := NEW Point {x: 1, y: 2}
+    q := &p.x
+    *q = *q + 1
+    r := &p
+    (*r).x = (*r).y
+    pxs := &xs
+    (*pxs)[0] = (*pxs)[1]
+    outer: FOR i := 10 TO 0 STEP -2 {
+        WHILE NOT (i < 3 OR i >= 8) AND i % 2 == 0 {
+            IF i == 4 { BREAK outer } ELSE IF i > 6 { CONTINUE outer } ELSE { BREAK }
+        }
+    }
+    MATCH NEW Rect {w: 2, h: 3} {
+        Rect[w, h] { print(w, h) }
+        ELSE {}
+    }
+    print(area(NEW Circle {radius: 2}, scale: 2), ...xs, xs[0] - -1, p.y / 2)
+    f := FUNCTION[] { RETURN FUNCTION[x] { RETURN x } }
+    RETURN f()(1)
+}
+"#;
+
+    // What --dump-ast prints is itself a program, which parses to the same thing
+    #[test]
+    fn dumped_definitions_parse_back_the_same() {
+        let dumped = dump(SOURCE);
+        assert_eq!(dump(&dumped), dumped);
+    }
+
+    #[test]
+    fn dumped_expressions_show_precedence() {
+        assert_eq!(dump("DEFINE x := 1 + 2 * 3 - 4"), "DEFINE x := ((1 + (2 * 3)) - 4)\n");
+        assert_eq!(dump("DEFINE x := NOT a OR b AND c == d"), "DEFINE x := ((NOT a) OR (b AND (c == d)))\n");
+        assert_eq!(dump("DEFINE x := (NOT TRUE) == FALSE"), "DEFINE x := ((NOT TRUE) == FALSE)\n");
+        assert_eq!(dump("DEFINE x := NOT TRUE == FALSE"), "DEFINE x := (NOT (TRUE == FALSE))\n");
+        assert_eq!(dump("DEFINE x := -f(1)[2].y"), "DEFINE x := (0 - f(1)[2].y)\n");
+    }
+
+    // Parsed differently without them, so the same text back is not enough to show it
+    #[test]
+    fn prefix_operands_keep_their_parentheses() {
+        assert_eq!(dump("DEFINE x := (*r).x"), "DEFINE x := (*r).x\n");
+        assert_eq!(dump("DEFINE x := *r.x"), "DEFINE x := *r.x\n");
+        assert_eq!(dump("DEFINE x := (*p)[0]"), "DEFINE x := (*p)[0]\n");
+        assert_eq!(dump("DEFINE x := *p[0]"), "DEFINE x := *p[0]\n");
+        assert_eq!(dump("DEFINE x := (*f)(1)"), "DEFINE x := (*f)(1)\n");
+    }
+
+    #[test]
+    fn errors_point_at_the_offending_token() {
+        assert_eq!(error("x := 1"), ("EXPECTED DEFINE, FOUND IDENT x".to_string(), 1, 1));
+        assert_eq!(error("DEFINE f := FUNCTION[a, a] {}"), ("ARGUMENT a DECLARED TWICE".to_string(), 1, 25));
+        assert_eq!(error("DEFINE f := FUNCTION[a := 1, b] {}"), ("ARGUMENT b NEEDS A DEFAULT".to_string(), 1, 30));
+        assert_eq!(error("DEFINE f := FUNCTION[] {\n  1 = 2\n}"), ("LEFT SIDE OF '=' IS NOT ASSIGNABLE".to_string(), 2, 3));
+        assert_eq!(error("DEFINE f := FUNCTION[] {\n    x := (1 +\n}"), ("EXPECTED EXPRESSION, FOUND '}'".to_string(), 3, 1));
+        assert_eq!(error("DEFINE x := 1e400"), ("1e400 IS TOO LARGE FOR A FLOAT".to_string(), 1, 13));
+        assert_eq!(error("DEFINE x := -1e400"), ("-1e400 IS TOO LARGE FOR A FLOAT".to_string(), 1, 13));
+    }
+
+    #[test]
+    fn loop_keywords_need_a_loop() {
+        assert_eq!(error("DEFINE f := FUNCTION[] {\n    BREAK\n}"), ("BREAK OUTSIDE OF A LOOP".to_string(), 2, 5));
+        assert_eq!(
+            error("DEFINE f := FUNCTION[] {\n    WHILE TRUE { BREAK nope }\n}"),
+            ("NO LOOP LABELLED nope AROUND BREAK".to_string(), 2, 18)
+        );
+    }
+
+    // The REPL asks for more input on these instead of reporting them, a line still ends a
+    // statement outside brackets
+    #[test]
+    fn unfinished_input_is_marked() {
+        assert!(parse("DEFINE x := f(1,", "test").err().unwrap().eof);
+        assert!(parse("DEFINE f := FUNCTION[] {\n    x := 1", "test").err().unwrap().eof);
+        assert!(!parse("DEFINE x := 1 +", "test").err().unwrap().eof);
+        assert!(!parse("DEFINE x := 1 )", "test").err().unwrap().eof);
+    }
+}
@@ -1,117 +1,316 @@
-pub use crate::core::{Expr, Cell, Stmt, Program};
+pub use crate::core::{Expr, Cell, Stmt, Program, Overflow};
 use crate::core::{Block, Definition};
 use crate::expressions::*;
 use crate::statements::*;
+use crate::records::*;
+use crate::unions::*;
 use crate::operations::*;
+use crate::span::Span;
 use std::rc::Rc;
 
+#[track_caller]
 pub fn text(s: &str) -> TextExpr {
-    TextExpr::new(s.to_string())
+    TextExpr::new(s.to_string(), Span::caller())
 }
 
+#[track_caller]
 pub fn int(s: &str) -> IntExpr {
-    IntExpr::new(s.parse().unwrap())
+    IntExpr::new(s.parse().unwrap(), Span::caller())
 }
 
+#[track_caller]
+pub fn float(s: &str) -> FloatExpr {
+    FloatExpr::new(s.parse().unwrap(), Span::caller())
+}
+
+#[track_caller]
 pub fn bool(b: bool) -> BoolExpr {
-    BoolExpr::new(b)
+    BoolExpr::new(b, Span::caller())
 }
 
+#[track_caller]
+pub fn unit() -> UnitExpr {
+    UnitExpr::new(Span::caller())
+}
+
+#[track_caller]
 pub fn var(s: &str) -> VarExpr {
-    VarExpr::new(s.to_string())
+    VarExpr::new(s.to_string(), Span::caller())
 }
 
-pub fn r#ref<C: Cell + Clone>(cell: C) -> RefExpr<C> {
-    RefExpr::new(cell)
+#[track_caller]
+pub fn r#ref<C: Cell>(cell: C) -> RefExpr<C> {
+    RefExpr::new(cell, Span::caller())
 }
 
-pub fn deref(pointer: Pointer) -> DerefExpr {
-    DerefExpr::new(pointer)
+#[track_caller]
+pub fn deref<E: Expr>(pointer: E) -> DerefExpr<E> {
+    DerefExpr::new(pointer, Span::caller())
 }
 
+#[track_caller]
 pub fn call<F: Expr>(expr: F, args: &[Rc<dyn Expr>]) -> CallExpr<F> {
-    CallExpr::new(expr, args.to_vec())
+    CallExpr::new(expr, args.iter().cloned().map(Arg::One).collect(), Span::caller())
 }
 
+// Mixes plain arguments with spread ones, built with Arg::One and Arg::Spread
+#[track_caller]
+pub fn call_spread<F: Expr>(expr: F, args: &[Arg]) -> CallExpr<F> {
+    CallExpr::new(expr, args.to_vec(), Span::caller())
+}
+
+// Named arguments follow the positional ones, as name and expression
+#[track_caller]
+pub fn call_named<F: Expr>(expr: F, args: &[Rc<dyn Expr>], named: &[(&str, Rc<dyn Expr>)]) -> CallExpr<F> {
+    CallExpr::new(expr, args.iter().cloned().map(Arg::One).collect(), Span::caller())
+        .named(named.iter().map(|(name, expr)| (name.to_string(), Rc::clone(expr))).collect())
+}
+
+#[track_caller]
+pub fn list(items: &[Rc<dyn Expr>]) -> ListExpr {
+    ListExpr::new(items.to_vec(), Span::caller())
+}
+
+#[track_caller]
+pub fn tuple(items: &[Rc<dyn Expr>]) -> TupleExpr {
+    TupleExpr::new(items.to_vec(), Span::caller())
+}
+
+#[track_caller]
+pub fn map(entries: &[Entry]) -> MapExpr {
+    MapExpr::new(entries.to_vec(), Span::caller())
+}
+
+#[track_caller]
+pub fn index<T: Expr, I: Expr>(target: T, index: I) -> IndexExpr<T, I> {
+    IndexExpr::new(target, index, Span::caller())
+}
+
+#[track_caller]
 pub fn add_var<E: Expr>(name: &str, expr: E) -> AddVarStmt<E> {
-    AddVarStmt::new(VarExpr::new(name.to_string()), expr)
+    AddVarStmt::new(VarExpr::new(name.to_string(), Span::caller()), expr)
+}
+
+#[track_caller]
+pub fn add_vars<E: Expr>(names: &[&str], expr: E) -> AddVarsStmt<E> {
+    let vars = names.iter().map(|name| VarExpr::new(name.to_string(), Span::caller())).collect();
+    AddVarsStmt::new(vars, expr, Span::caller())
 }
 
+#[track_caller]
 pub fn change<C: Cell, E: Expr>(cell: C, expr: E) -> ChangeStmt<C, E> {
-    ChangeStmt::new(cell, expr)
+    ChangeStmt::new(cell, expr, Span::caller())
 }
 
 pub fn eval<E: Expr>(expr: E) -> EvalStmt<E> {
     EvalStmt::new(expr)
 }
 
+#[track_caller]
+pub fn block(body: &[Rc<dyn Stmt>]) -> Block {
+    Block::new(body.to_vec(), Span::caller())
+}
+
+#[track_caller]
+pub fn r#if<C: Expr>(cond: C, then: &[Rc<dyn Stmt>]) -> IfStmt<C> {
+    IfStmt::new(cond, block(then), None, Span::caller())
+}
+
+// The otherwise branch is a block or another if, which makes an ELSE IF chain
+#[track_caller]
+pub fn if_else<C: Expr, S: Stmt + 'static>(cond: C, then: &[Rc<dyn Stmt>], otherwise: S) -> IfStmt<C> {
+    IfStmt::new(cond, block(then), Some(Rc::new(otherwise)), Span::caller())
+}
+
+// Loops take a label or a step through WhileStmt::labelled, ForStmt::labelled and ForStmt::step
+#[track_caller]
+pub fn r#while<C: Expr>(cond: C, body: &[Rc<dyn Stmt>]) -> WhileStmt<C> {
+    WhileStmt::new(cond, block(body), Span::caller())
+}
+
+#[track_caller]
+pub fn r#for<S: Expr, E: Expr>(name: &str, start: S, end: E, body: &[Rc<dyn Stmt>]) -> ForStmt<S, E> {
+    ForStmt::new(VarExpr::new(name.to_string(), Span::caller()), start, end, block(body), Span::caller())
+}
+
+#[track_caller]
+pub fn r#break() -> BreakStmt {
+    BreakStmt::new(None, Span::caller())
+}
+
+#[track_caller]
+pub fn break_to(label: &str) -> BreakStmt {
+    BreakStmt::new(Some(label.to_string()), Span::caller())
+}
+
+#[track_caller]
+pub fn r#continue() -> ContinueStmt {
+    ContinueStmt::new(None, Span::caller())
+}
+
+#[track_caller]
+pub fn continue_to(label: &str) -> ContinueStmt {
+    ContinueStmt::new(Some(label.to_string()), Span::caller())
+}
+
+#[track_caller]
+pub fn r#return<E: Expr>(expr: E) -> ReturnStmt {
+    ReturnStmt::new(Some(Rc::new(expr)), Span::caller())
+}
+
+#[track_caller]
+pub fn return_unit() -> ReturnStmt {
+    ReturnStmt::new(None, Span::caller())
+}
+
+#[track_caller]
 pub fn r#const<E: Expr>(name: &str, expr: E) -> Definition {
     Definition::new(add_var(name, expr))
 }
 
+#[track_caller]
 pub fn define(name: &str, args: &[&str], body: &[Rc<dyn Stmt>]) -> Definition {
     Definition::new(
         add_var(
             name,
             Function::new(
                 name.to_string(),
-                args.into_iter()
+                args.iter()
                     .map(|x| x.to_string())
                     .collect::<Vec<_>>(),
-                Block::new(body.to_vec())
+                Block::new(body.to_vec(), Span::caller()),
+                Span::caller()
             )
         )
     )
 }
 
+// The defaults belong to the last arguments, one each
+#[track_caller]
+pub fn define_defaults(name: &str, args: &[&str], defaults: &[Rc<dyn Expr>], body: &[Rc<dyn Stmt>]) -> Definition {
+    Definition::new(
+        add_var(
+            name,
+            Function::new(
+                name.to_string(),
+                args.iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>(),
+                Block::new(body.to_vec(), Span::caller()),
+                Span::caller()
+            ).defaults(defaults.to_vec())
+        )
+    )
+}
+
+#[track_caller]
+pub fn record(name: &str, fields: &[&str]) -> Definition {
+    Definition::record(RecordType::new(
+        name.to_string(),
+        fields.iter().map(|x| x.to_string()).collect(),
+        Span::caller()
+    ))
+}
+
+#[track_caller]
+pub fn union(name: &str, variants: &[(&str, &[&str])]) -> Definition {
+    Definition::union(
+        Union::new(name.to_string(), variants.iter().map(|(name, _)| name.to_string()).collect(), Span::caller()),
+        variants.iter().map(|(_, fields)| fields.iter().map(|x| x.to_string()).collect()).collect()
+    )
+}
+
+#[track_caller]
+pub fn pattern(variant: &str, names: &[&str]) -> Pattern {
+    Pattern::new(variant.to_string(), Some(names.iter().map(|x| x.to_string()).collect()), Span::caller())
+}
+
+// Arms are built with pattern and block, ELSE is the otherwise block
+#[track_caller]
+pub fn r#match<E: Expr>(subject: E, arms: Vec<(Pattern, Block)>, otherwise: Option<Block>) -> MatchStmt<E> {
+    MatchStmt::new(subject, arms, otherwise, Span::caller())
+}
+
+#[track_caller]
+pub fn new(kind: &str, fields: &[(&str, Rc<dyn Expr>)]) -> NewExpr<VarExpr> {
+    NewExpr::new(
+        var(kind),
+        fields.iter().map(|(name, expr)| (name.to_string(), Rc::clone(expr))).collect(),
+        Span::caller()
+    )
+}
+
+#[track_caller]
+pub fn field<E: Expr>(record: E, name: &str) -> FieldExpr<E> {
+    FieldExpr::new(record, name.to_string(), Span::caller())
+}
+
+#[track_caller]
 pub fn add<Lhs: Expr, Rhs: Expr>(x: Lhs, y: Rhs) -> AddExpr<Lhs, Rhs> {
-    AddExpr::new(x, y)
+    AddExpr::new(x, y, Span::caller())
 }
 
+#[track_caller]
 pub fn sub<Lhs: Expr, Rhs: Expr>(x: Lhs, y: Rhs) -> SubExpr<Lhs, Rhs> {
-    SubExpr::new(x, y)
+    SubExpr::new(x, y, Span::caller())
 }
 
+#[track_caller]
 pub fn mul<Lhs: Expr, Rhs: Expr>(x: Lhs, y: Rhs) -> MulExpr<Lhs, Rhs> {
-    MulExpr::new(x, y)
+    MulExpr::new(x, y, Span::caller())
 }
 
+#[track_caller]
 pub fn div<Lhs: Expr, Rhs: Expr>(x: Lhs, y: Rhs) -> DivExpr<Lhs, Rhs> {
-    DivExpr::new(x, y)
+    DivExpr::new(x, y, Span::caller())
 }
 
+#[track_caller]
 pub fn r#mod<Lhs: Expr, Rhs: Expr>(x: Lhs, y: Rhs) -> ModExpr<Lhs, Rhs> {
-    ModExpr::new(x, y)
+    ModExpr::new(x, y, Span::caller())
 }
 
+#[track_caller]
 pub fn and<Lhs: Expr, Rhs: Expr>(x: Lhs, y: Rhs) -> AndExpr<Lhs, Rhs> {
-    AndExpr::new(x, y)
+    AndExpr::new(x, y, Span::caller())
 }
 
+#[track_caller]
 pub fn or<Lhs: Expr, Rhs: Expr>(x: Lhs, y: Rhs) -> OrExpr<Lhs, Rhs> {
-    OrExpr::new(x, y)
+    OrExpr::new(x, y, Span::caller())
 }
 
+#[track_caller]
 pub fn not<E: Expr>(expr: E) -> NotExpr<E> {
-    NotExpr::new(expr)
+    NotExpr::new(expr, Span::caller())
 }
 
+#[track_caller]
 pub fn lt<Lhs: Expr, Rhs: Expr>(x: Lhs, y: Rhs) -> LtExpr<Lhs, Rhs> {
-    LtExpr::new(x, y)
+    LtExpr::new(x, y, Span::caller())
 }
 
+#[track_caller]
 pub fn le<Lhs: Expr, Rhs: Expr>(x: Lhs, y: Rhs) -> LeExpr<Lhs, Rhs> {
-    LeExpr::new(x, y)
+    LeExpr::new(x, y, Span::caller())
 }
 
+#[track_caller]
 pub fn eq<Lhs: Expr, Rhs: Expr>(x: Lhs, y: Rhs) -> EqExpr<Lhs, Rhs> {
-    EqExpr::new(x, y)
+    EqExpr::new(x, y, Span::caller())
 }
 
+#[track_caller]
 pub fn ge<Lhs: Expr, Rhs: Expr>(x: Lhs, y: Rhs) -> GeExpr<Lhs, Rhs> {
-    GeExpr::new(x, y)
+    GeExpr::new(x, y, Span::caller())
 }
 
+#[track_caller]
 pub fn gt<Lhs: Expr, Rhs: Expr>(x: Lhs, y: Rhs) -> GtExpr<Lhs, Rhs> {
-    GtExpr::new(x, y)
+    GtExpr::new(x, y, Span::caller())
+}
+
+#[track_caller]
+pub fn cond<C: Expr, T: Expr, F: Expr>(cond: C, then: T, otherwise: F) -> CondExpr<C, T, F> {
+    CondExpr::new(cond, then, otherwise, Span::caller())
 }
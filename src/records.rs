@@ -0,0 +1,201 @@
+use crate::core::{Expr, Cell, Bindings};
+use crate::unions::Union;
+use crate::expressions::postfix_operand;
+use crate::value::{Value, Slot};
+use crate::error::{Error, ErrorKind, Result};
+use crate::span::Span;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Declared by a RECORD definition, which binds it under its name like any other global,
+// or as one variant of a UNION
+pub struct RecordType {
+    name: String,
+    fields: Vec<String>,
+    union: Option<Rc<Union>>,
+    span: Span
+}
+
+impl RecordType {
+    pub fn new(name: String, fields: Vec<String>, span: Span) -> RecordType {
+        RecordType { name, fields, union: None, span }
+    }
+
+    pub fn variant(name: String, fields: Vec<String>, union: Rc<Union>, span: Span) -> RecordType {
+        RecordType { name, fields, union: Some(union), span }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn union(&self) -> Option<&Union> {
+        self.union.as_deref()
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    fn field(&self, field: &str) -> Result<usize> {
+        self.fields.iter().position(|x| x == field).ok_or_else(|| ErrorKind::UnknownField {
+            record: self.name.as_str().into(),
+            field: field.to_string()
+        }.into())
+    }
+
+    pub fn signature(&self) -> String {
+        format!("{}[{}]", self.name, self.fields.join(", "))
+    }
+
+    pub fn string(&self) -> String {
+        match &self.union {
+            Some(union) => format!("VARIANT {} OF {}", self.signature(), union.name()),
+            None => format!("RECORD {}", self.signature())
+        }
+    }
+}
+
+// Shared like lists and maps, the fields are kept in the order the type declares them
+pub struct Record {
+    kind: Rc<RecordType>,
+    fields: RefCell<Vec<Value>>
+}
+
+impl Record {
+    pub fn kind(&self) -> &RecordType {
+        &self.kind
+    }
+
+    pub fn field(&self, index: usize) -> Value {
+        self.fields.borrow()[index].clone()
+    }
+
+    pub fn set_field(&self, index: usize, value: Value) {
+        self.fields.borrow_mut()[index] = value;
+    }
+
+    pub(crate) fn values(&self) -> &RefCell<Vec<Value>> {
+        &self.fields
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&String, Value)> {
+        self.kind.fields.iter().zip(self.fields.borrow().clone())
+    }
+
+    pub fn string(&self) -> String {
+        self.show(&mut Vec::new())
+    }
+
+    // Takes part in cutting cycles short for Value::show, a record is known by its address
+    pub(crate) fn show(&self, seen: &mut Vec<usize>) -> String {
+        let address = self as *const Record as usize;
+        if seen.contains(&address) {
+            return format!("{} {{...}}", self.kind.name);
+        }
+
+        seen.push(address);
+        let fields: Vec<String> = self.fields()
+            .map(|(name, value)| format!("{name}: {}", value.show(seen)))
+            .collect();
+        seen.pop();
+
+        format!("{} {{{}}}", self.kind.name, fields.join(", "))
+    }
+}
+
+// Every field has to be given exactly once, the parser already rejects repeats
+pub struct NewExpr<T: Expr> {
+    kind: T,
+    fields: Vec<(String, Rc<dyn Expr>)>,
+    span: Span
+}
+
+impl<T: Expr> NewExpr<T> {
+    pub fn new(kind: T, fields: Vec<(String, Rc<dyn Expr>)>, span: Span) -> NewExpr<T> {
+        NewExpr { kind, fields, span }
+    }
+}
+
+impl<T: Expr> Expr for NewExpr<T> {
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        let kind = match self.kind.value(bindings)? {
+            Value::RecordType(kind) => kind,
+            value => return Err(Error::new(ErrorKind::TypeMismatch { expected: "TYPE", found: value.type_name() }, self.kind.span()))
+        };
+
+        let mut values = vec![None; kind.fields.len()];
+        for (name, expr) in &self.fields {
+            let i = kind.field(name).map_err(|err| err.at(expr.span()))?;
+            values[i] = Some(expr.value(bindings)?);
+        }
+
+        let mut fields = Vec::new();
+        for (name, value) in kind.fields.iter().zip(values) {
+            match value {
+                Some(value) => fields.push(value),
+                None => {
+                    let missing = ErrorKind::MissingField { record: kind.name.as_str().into(), field: name.clone() };
+                    return Err(Error::new(missing, &self.span));
+                }
+            }
+        }
+
+        Ok(Value::Record(Rc::new(Record { kind, fields: RefCell::new(fields) })))
+    }
+
+    fn string(&self) -> String {
+        let fields: Vec<String> = self.fields.iter()
+            .map(|(name, expr)| format!("{name}: {}", expr.string()))
+            .collect();
+        format!("NEW {} {{{}}}", self.kind.string(), fields.join(", "))
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+#[derive(Clone)]
+pub struct FieldExpr<E: Expr> {
+    record: E,
+    field: String,
+    span: Span
+}
+
+impl<E: Expr> FieldExpr<E> {
+    pub fn new(record: E, field: String, span: Span) -> FieldExpr<E> {
+        FieldExpr { record, field, span }
+    }
+
+}
+
+impl<E: Expr> Expr for FieldExpr<E> {
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        self.slot(bindings)?.get()
+    }
+
+    fn string(&self) -> String {
+        format!("{}.{}", postfix_operand(&self.record), self.field)
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+impl<E: Expr> Cell for FieldExpr<E> {
+    fn change(&self, bindings: &mut Bindings, value: Value) -> Result<()> {
+        self.slot(bindings)?.set(value)
+    }
+
+    fn slot(&self, bindings: &mut Bindings) -> Result<Slot> {
+        let record = match self.record.value(bindings)? {
+            Value::Record(record) => record,
+            value => return Err(Error::new(ErrorKind::TypeMismatch { expected: "RECORD", found: value.type_name() }, self.record.span()))
+        };
+
+        let i = record.kind.field(&self.field).map_err(|err| err.at(&self.span))?;
+        Ok(Slot::Field(record, i))
+    }
+}
@@ -0,0 +1,231 @@
+use crate::core::{Expr, Definition, Program, RECURSION_LIMIT};
+use crate::parser::{Parser, ParseError};
+use crate::error::Error;
+use crate::value::Value;
+use crate::span::Sources;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+const HELP: &str = "\
+DEFINE name := expr    Add a global definition
+RECORD name[fields]    Add a record type
+UNION name[variants]   Add a union of record types
+expr                   Evaluate an expression and print its value
+:globals               List the global bindings
+:type expr             Print the type of an expression's value
+:reset                 Forget every definition
+:help                  Print this message
+:quit                  Leave the REPL
+
+A command given while an input is unfinished drops that input";
+
+enum Input {
+    Definitions(Vec<Definition>),
+    Expr(Rc<dyn Expr>)
+}
+
+fn parse_input(source: &str, file: &str) -> Result<Input, ParseError> {
+    let mut parser = Parser::new(source, file)?;
+
+    if parser.at_definition() {
+        return Ok(Input::Definitions(parser.parse_program()?));
+    }
+
+    let expr = parser.parse_expr()?;
+    if !parser.at_end() {
+        return Err(parser.unexpected("END OF INPUT"));
+    }
+
+    Ok(Input::Expr(expr))
+}
+
+pub struct Repl {
+    program: Program,
+    sources: Sources,
+    inputs: usize,
+    recursion_limit: usize
+}
+
+impl Default for Repl {
+    fn default() -> Repl {
+        Repl::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl { program: Program::new(), sources: Sources::new(), inputs: 0, recursion_limit: RECURSION_LIMIT }
+    }
+
+    // Kept across :reset, see Program::set_recursion_limit
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.recursion_limit = limit;
+        self.program.set_recursion_limit(limit);
+    }
+
+    // Every input is kept so errors inside earlier definitions can still show their source,
+    // an unfinished input is overwritten by its continuation until it is complete
+    fn source(&mut self, text: &str) -> String {
+        let file = format!("<input {}>", self.inputs + 1);
+        self.sources.add(&file, text);
+        file
+    }
+
+    fn runtime_error(&self, err: Error) {
+        println!("{}", err.render(&self.sources));
+    }
+
+    fn syntax_error(&self, err: ParseError) {
+        println!("{}", err.render(&self.sources));
+    }
+
+    fn globals(&self) {
+        let mut globals = self.program.bindings().globals().entries();
+        globals.sort_by(|x, y| x.0.cmp(&y.0));
+
+        for (name, value) in globals {
+            println!("{name} := {}", value.string());
+        }
+    }
+
+    fn type_of(&mut self, source: &str) -> Result<(), ParseError> {
+        let file = self.source(source);
+        let mut parser = Parser::new(source, &file)?;
+        let expr = parser.parse_expr()?;
+        if !parser.at_end() {
+            return Err(parser.unexpected("END OF INPUT"));
+        }
+
+        match self.program.eval(&expr) {
+            Ok(value) => println!("{}", value.type_name()),
+            Err(err) => self.runtime_error(err)
+        }
+
+        Ok(())
+    }
+
+    fn eval(&mut self, source: &str) -> Result<(), ParseError> {
+        let file = self.source(source);
+
+        match parse_input(source, &file)? {
+            Input::Definitions(defs) => {
+                for def in defs {
+                    self.program.add(def);
+                }
+
+                if let Err(err) = self.program.load() {
+                    self.runtime_error(err);
+                }
+            }
+            // Unit is left unprinted so print(x) does not echo a second line
+            Input::Expr(expr) => match self.program.eval(&expr) {
+                Ok(Value::Unit) => {}
+                Ok(value) => println!("{}", value.string()),
+                Err(err) => self.runtime_error(err)
+            }
+        }
+
+        Ok(())
+    }
+
+    // Returns false once the user asks to leave
+    fn command(&mut self, line: &str) -> bool {
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+        let res = match command {
+            ":quit" | ":q" => return false,
+            ":help" => {
+                println!("{HELP}");
+                Ok(())
+            }
+            ":reset" => {
+                self.program = Program::new();
+                self.program.set_recursion_limit(self.recursion_limit);
+                Ok(())
+            }
+            ":globals" => {
+                self.globals();
+                Ok(())
+            }
+            ":type" => {
+                let res = self.type_of(rest);
+                self.inputs += 1;
+                res
+            }
+            _ => {
+                println!("UNKNOWN COMMAND {command}, TRY :help");
+                Ok(())
+            }
+        };
+
+        if let Err(err) = res {
+            self.syntax_error(err);
+        }
+
+        true
+    }
+
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        let mut buffer = String::new();
+
+        loop {
+            print!("{}", if buffer.is_empty() { ">>> " } else { "... " });
+            io::stdout().flush().unwrap();
+
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                _ => break
+            };
+
+            let trimmed = line.trim();
+            if buffer.is_empty() && trimmed.is_empty() { continue; }
+
+            if trimmed.starts_with(':') {
+                buffer.clear();
+                if self.command(trimmed) { continue; }
+                break;
+            }
+
+            buffer.push_str(&line);
+            buffer.push('\n');
+
+            match self.eval(&buffer) {
+                Err(err) if err.eof => continue,
+                Err(err) => self.syntax_error(err),
+                Ok(()) => {}
+            }
+
+            self.inputs += 1;
+            buffer.clear();
+        }
+
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_definition(source: &str) -> bool {
+        matches!(parse_input(source, "test").unwrap(), Input::Definitions(_))
+    }
+
+    #[test]
+    fn definitions_are_told_apart_by_their_first_token() {
+        assert!(is_definition("DEFINE x := 1"));
+        assert!(is_definition("# a note\n\nRECORD P[x]"));
+        assert!(is_definition("  UNION U[A[], B[]]"));
+        assert!(!is_definition("RECORDS"));
+        assert!(!is_definition("DEFINED + 1"));
+        assert!(!is_definition("UNIONS[0]"));
+    }
+
+    #[test]
+    fn an_expression_must_be_the_whole_input() {
+        let err = parse_input("DEFINED := 3", "test").err().unwrap();
+        assert_eq!(err.message, "EXPECTED END OF INPUT, FOUND ':='");
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::panic::Location;
+use std::rc::Rc;
+use std::fmt::{self, Write};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub file: Rc<str>,
+    pub line: usize,
+    pub col: usize,
+    pub len: usize
+}
+
+impl Span {
+    pub fn new(file: Rc<str>, line: usize, col: usize, len: usize) -> Span {
+        Span { file, line, col, len }
+    }
+
+    // Stretches the span over everything up to the end of another one on the same line
+    pub fn to(&self, end: &Span) -> Span {
+        let mut span = self.clone();
+        if end.file == self.file && end.line == self.line && end.col >= self.col {
+            span.len = end.col + end.len - self.col;
+        }
+        span
+    }
+
+    // Nodes built from Rust through the prelude point at the line that built them
+    #[track_caller]
+    pub fn caller() -> Span {
+        let location = Location::caller();
+        Span::new(location.file().into(), location.line() as usize, location.column() as usize, 1)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+#[derive(Default)]
+pub struct Sources(HashMap<Rc<str>, Rc<str>>);
+
+impl Sources {
+    pub fn new() -> Sources {
+        Sources(HashMap::new())
+    }
+
+    pub fn add(&mut self, file: &str, text: &str) {
+        self.0.insert(file.into(), text.into());
+    }
+
+    pub fn line(&self, span: &Span) -> Option<&str> {
+        let text = self.0.get(&span.file)?;
+        let index = span.line.checked_sub(1)?;
+
+        // The end of a file that finishes with a newline sits on an empty line of its own
+        match text.lines().nth(index) {
+            Some(line) => Some(line),
+            None if index == text.lines().count() => Some(""),
+            None => None
+        }
+    }
+
+    pub fn render(&self, title: &str, message: &str, span: Option<&Span>) -> String {
+        let mut res = format!("{title}: {message}");
+
+        let span = match span {
+            Some(span) => span,
+            None => return res
+        };
+
+        let line = match self.line(span) {
+            Some(line) => line,
+            None => {
+                write!(&mut res, "\n --> {span}").unwrap();
+                return res;
+            }
+        };
+
+        let number = span.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        // Keep tabs in the padding so the carets line up with the source line
+        let padding = line.chars()
+            .take(span.col.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let width = line.chars().count().saturating_sub(span.col.saturating_sub(1)).clamp(1, span.len.max(1));
+
+        write!(&mut res, "\n{gutter}--> {span}").unwrap();
+        write!(&mut res, "\n{gutter} |").unwrap();
+        write!(&mut res, "\n{number} | {line}").unwrap();
+        write!(&mut res, "\n{gutter} | {padding}{}", "^".repeat(width)).unwrap();
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(text: &str, line: usize, col: usize, len: usize) -> String {
+        let mut sources = Sources::new();
+        sources.add("test", text);
+        sources.render("SYNTAX ERROR", "BAD", Some(&Span::new("test".into(), line, col, len)))
+    }
+
+    #[test]
+    fn carets_underline_the_span() {
+        assert_eq!(render("x := 1\ny := oops + 2\n", 2, 6, 4), "\
+SYNTAX ERROR: BAD
+ --> test:2:6
+  |
+2 | y := oops + 2
+  |      ^^^^");
+    }
+
+    #[test]
+    fn the_gutter_fits_the_line_number() {
+        let text = "\n".repeat(11) + "bad";
+        assert_eq!(render(&text, 12, 1, 3), "\
+SYNTAX ERROR: BAD
+  --> test:12:1
+   |
+12 | bad
+   | ^^^");
+    }
+
+    #[test]
+    fn tabs_stay_in_the_padding() {
+        assert_eq!(render("\tx := \ty", 1, 8, 1), "\
+SYNTAX ERROR: BAD
+ --> test:1:8
+  |
+1 | \tx := \ty
+  | \t     \t^");
+    }
+
+    #[test]
+    fn carets_stop_at_the_end_of_the_line() {
+        assert_eq!(render("ab", 1, 2, 10), "\
+SYNTAX ERROR: BAD
+ --> test:1:2
+  |
+1 | ab
+  |  ^");
+    }
+
+    #[test]
+    fn the_end_of_a_file_ending_in_a_newline_is_an_empty_line() {
+        assert_eq!(render("x := (\n", 2, 1, 1), "\
+SYNTAX ERROR: BAD
+ --> test:2:1
+  |
+2 | 
+  | ^");
+    }
+
+    #[test]
+    fn without_the_source_only_the_place_is_given() {
+        let span = Span::new("elsewhere".into(), 3, 4, 1);
+        assert_eq!(Sources::new().render("RUNTIME ERROR", "BAD", Some(&span)), "RUNTIME ERROR: BAD\n --> elsewhere:3:4");
+        assert_eq!(Sources::new().render("RUNTIME ERROR", "BAD", None), "RUNTIME ERROR: BAD");
+    }
+}
@@ -1,5 +1,10 @@
-use crate::core::{Expr, Cell, Stmt, Bindings};
+use crate::core::{Expr, Cell, Stmt, Bindings, Block, Flow};
+use crate::error::{Error, ErrorKind, Result};
+use crate::span::Span;
 use crate::expressions::VarExpr;
+use crate::value::Value;
+use crate::operations::{condition, int};
+use std::rc::Rc;
 
 pub struct AddVarStmt<E: Expr> {
     var: VarExpr,
@@ -13,36 +18,93 @@ impl<E: Expr> AddVarStmt<E> {
 }
 
 impl<E: Expr> Stmt for AddVarStmt<E> {
-    fn execute(&self, bindings: &mut Bindings) {
-        let value = self.expr.value(bindings);
-        bindings.add(self.var.string(), value);
+    fn execute(&self, bindings: &mut Bindings) -> Result<Flow> {
+        let value = self.expr.value(bindings)?;
+        bindings.add(self.var.string(), value).map_err(|err| err.at(self.var.span()))?;
+        Ok(Flow::Next)
     }
 
     fn string(&self) -> String {
         format!("{} := {}", self.var.string(), self.expr.string())
     }
+
+    fn span(&self) -> &Span {
+        self.var.span()
+    }
+}
+
+// Binds each name to the matching item of a tuple or list with exactly as many items
+pub struct AddVarsStmt<E: Expr> {
+    vars: Vec<VarExpr>,
+    expr: E,
+    span: Span
+}
+
+impl<E: Expr> AddVarsStmt<E> {
+    pub fn new(vars: Vec<VarExpr>, expr: E, span: Span) -> AddVarsStmt<E> {
+        AddVarsStmt { vars, expr, span }
+    }
+}
+
+impl<E: Expr> Stmt for AddVarsStmt<E> {
+    fn execute(&self, bindings: &mut Bindings) -> Result<Flow> {
+        let items: Vec<Value> = match self.expr.value(bindings)? {
+            Value::Tuple(items) => items.to_vec(),
+            Value::List(list) => list.borrow().clone(),
+            value => {
+                let kind = ErrorKind::TypeMismatch { expected: "TUPLE", found: value.type_name() };
+                return Err(Error::new(kind, self.expr.span()));
+            }
+        };
+
+        if items.len() != self.vars.len() {
+            let kind = ErrorKind::UnpackMismatch { expected: self.vars.len(), found: items.len() };
+            return Err(Error::new(kind, self.expr.span()));
+        }
+
+        for (var, value) in self.vars.iter().zip(items) {
+            bindings.add(var.string(), value).map_err(|err| err.at(var.span()))?;
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn string(&self) -> String {
+        let vars: Vec<String> = self.vars.iter().map(|var| var.string()).collect();
+        format!("({}) := {}", vars.join(", "), self.expr.string())
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 pub struct ChangeStmt<C: Cell, E: Expr> {
     cell: C,
-    expr: E
+    expr: E,
+    span: Span
 }
 
 impl<C: Cell, E: Expr> ChangeStmt<C, E> {
-    pub fn new(cell: C, expr: E) -> ChangeStmt<C, E> {
-        ChangeStmt { cell, expr }
+    pub fn new(cell: C, expr: E, span: Span) -> ChangeStmt<C, E> {
+        ChangeStmt { cell, expr, span }
     }
 }
 
 impl<C: Cell, E: Expr> Stmt for ChangeStmt<C, E> {  
-    fn execute(&self, bindings: &mut Bindings) {
-        let value = self.expr.value(bindings);
-        self.cell.change(bindings, value);
+    fn execute(&self, bindings: &mut Bindings) -> Result<Flow> {
+        let value = self.expr.value(bindings)?;
+        self.cell.change(bindings, value)?;
+        Ok(Flow::Next)
     }
 
     fn string(&self) -> String {
         format!("{} = {}", self.cell.string(), self.expr.string())
     }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
 pub struct EvalStmt<E: Expr> {
@@ -56,11 +118,378 @@ impl<E: Expr> EvalStmt<E> {
 }
 
 impl<E: Expr> Stmt for EvalStmt<E> {  
-    fn execute(&self, bindings: &mut Bindings) {
-        self.expr.value(bindings);
+    fn execute(&self, bindings: &mut Bindings) -> Result<Flow> {
+        self.expr.value(bindings)?;
+        Ok(Flow::Next)
     }
 
     fn string(&self) -> String {
         self.expr.string()
     }
+
+    fn span(&self) -> &Span {
+        self.expr.span()
+    }
+}
+
+// An ELSE IF chain is an IfStmt sitting in the otherwise branch
+pub struct IfStmt<C: Expr> {
+    cond: C,
+    then: Block,
+    otherwise: Option<Rc<dyn Stmt>>,
+    span: Span
+}
+
+impl<C: Expr> IfStmt<C> {
+    pub fn new(cond: C, then: Block, otherwise: Option<Rc<dyn Stmt>>, span: Span) -> IfStmt<C> {
+        IfStmt { cond, then, otherwise, span }
+    }
+}
+
+impl<C: Expr> Stmt for IfStmt<C> {
+    fn execute(&self, bindings: &mut Bindings) -> Result<Flow> {
+        if condition(&self.cond, bindings)? {
+            self.then.execute(bindings)
+        } else if let Some(otherwise) = &self.otherwise {
+            otherwise.execute(bindings)
+        } else {
+            Ok(Flow::Next)
+        }
+    }
+
+    fn string(&self) -> String {
+        let res = format!("IF {} {}", self.cond.string(), self.then.string());
+
+        match &self.otherwise {
+            Some(otherwise) => format!("{res} ELSE {}", otherwise.string()),
+            None => res
+        }
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+// None keeps the loop going, Some(flow) leaves it and hands flow to the enclosing statement
+fn leave(label: &Option<String>, flow: Flow) -> Option<Flow> {
+    match flow {
+        Flow::Next => None,
+        Flow::Continue(target) if target.is_none() || target == *label => None,
+        Flow::Break(target) if target.is_none() || target == *label => Some(Flow::Next),
+        flow => Some(flow)
+    }
+}
+
+fn label_prefix(label: &Option<String>) -> String {
+    match label {
+        Some(label) => format!("{label}: "),
+        None => String::new()
+    }
+}
+
+pub struct WhileStmt<C: Expr> {
+    label: Option<String>,
+    cond: C,
+    body: Block,
+    span: Span
+}
+
+impl<C: Expr> WhileStmt<C> {
+    pub fn new(cond: C, body: Block, span: Span) -> WhileStmt<C> {
+        WhileStmt { label: None, cond, body, span }
+    }
+
+    pub fn labelled(mut self, label: &str) -> WhileStmt<C> {
+        self.label = Some(label.to_string());
+        self
+    }
+}
+
+impl<C: Expr> Stmt for WhileStmt<C> {
+    fn execute(&self, bindings: &mut Bindings) -> Result<Flow> {
+        while condition(&self.cond, bindings)? {
+            if let Some(flow) = leave(&self.label, self.body.execute(bindings)?) {
+                return Ok(flow);
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn string(&self) -> String {
+        format!("{}WHILE {} {}", label_prefix(&self.label), self.cond.string(), self.body.string())
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+// Counts from start to end inclusive, the counter lives in a frame of its own around each iteration
+pub struct ForStmt<S: Expr, E: Expr> {
+    label: Option<String>,
+    var: VarExpr,
+    start: S,
+    end: E,
+    step: Option<Rc<dyn Expr>>,
+    body: Block,
+    span: Span
+}
+
+impl<S: Expr, E: Expr> ForStmt<S, E> {
+    pub fn new(var: VarExpr, start: S, end: E, body: Block, span: Span) -> ForStmt<S, E> {
+        ForStmt { label: None, var, start, end, step: None, body, span }
+    }
+
+    pub fn labelled(mut self, label: &str) -> ForStmt<S, E> {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    pub fn step<T: Expr>(mut self, step: T) -> ForStmt<S, E> {
+        self.step = Some(Rc::new(step));
+        self
+    }
+}
+
+impl<S: Expr, E: Expr> Stmt for ForStmt<S, E> {
+    fn execute(&self, bindings: &mut Bindings) -> Result<Flow> {
+        let start = int(&self.start, bindings)?;
+        let end = int(&self.end, bindings)?;
+        let step = match &self.step {
+            Some(step) => match int(step, bindings)? {
+                0 => return Err(Error::new(ErrorKind::ZeroStep, step.span())),
+                n => n
+            },
+            None => 1
+        };
+
+        let mut i = start;
+        while (step > 0 && i <= end) || (step < 0 && i >= end) {
+            bindings.new_frame();
+            let res = bindings.add(self.var.string(), Value::Int(i))
+                .and_then(|_| self.body.execute(bindings));
+            bindings.pop_frame();
+
+            if let Some(flow) = leave(&self.label, res?) {
+                return Ok(flow);
+            }
+
+            i = match i.checked_add(step) {
+                Some(i) => i,
+                None => break
+            };
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn string(&self) -> String {
+        let step = match &self.step {
+            Some(step) => format!(" STEP {}", step.string()),
+            None => String::new()
+        };
+
+        format!(
+            "{}FOR {} := {} TO {}{step} {}",
+            label_prefix(&self.label),
+            self.var.string(),
+            self.start.string(),
+            self.end.string(),
+            self.body.string()
+        )
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+pub struct BreakStmt {
+    label: Option<String>,
+    span: Span
+}
+
+impl BreakStmt {
+    pub fn new(label: Option<String>, span: Span) -> BreakStmt {
+        BreakStmt { label, span }
+    }
+}
+
+impl Stmt for BreakStmt {
+    fn execute(&self, _bindings: &mut Bindings) -> Result<Flow> {
+        Ok(Flow::Break(self.label.clone()))
+    }
+
+    fn string(&self) -> String {
+        match &self.label {
+            Some(label) => format!("BREAK {label}"),
+            None => "BREAK".to_string()
+        }
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+pub struct ContinueStmt {
+    label: Option<String>,
+    span: Span
+}
+
+impl ContinueStmt {
+    pub fn new(label: Option<String>, span: Span) -> ContinueStmt {
+        ContinueStmt { label, span }
+    }
+}
+
+impl Stmt for ContinueStmt {
+    fn execute(&self, _bindings: &mut Bindings) -> Result<Flow> {
+        Ok(Flow::Continue(self.label.clone()))
+    }
+
+    fn string(&self) -> String {
+        match &self.label {
+            Some(label) => format!("CONTINUE {label}"),
+            None => "CONTINUE".to_string()
+        }
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+pub struct ReturnStmt {
+    expr: Option<Rc<dyn Expr>>,
+    span: Span
+}
+
+impl ReturnStmt {
+    pub fn new(expr: Option<Rc<dyn Expr>>, span: Span) -> ReturnStmt {
+        ReturnStmt { expr, span }
+    }
+}
+
+impl Stmt for ReturnStmt {
+    fn execute(&self, bindings: &mut Bindings) -> Result<Flow> {
+        match &self.expr {
+            Some(expr) => expr.tail(bindings),
+            None => Ok(Flow::Return(Value::Unit))
+        }
+    }
+
+    fn string(&self) -> String {
+        match &self.expr {
+            Some(expr) => format!("RETURN {}", expr.string()),
+            None => "RETURN".to_string()
+        }
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Program;
+    use crate::parser::{parse, Parser};
+
+    // The value of expr once source is loaded, or the error it raises with where it points
+    fn eval(source: &str, expr: &str) -> Result<String, String> {
+        let mut program = Program::new();
+        for def in parse(source, "test").unwrap() {
+            program.add(def);
+        }
+        program.load().unwrap();
+
+        let expr = Parser::new(expr, "test").unwrap().parse_expr().unwrap();
+        program.eval(&expr).map(|value| value.string()).map_err(|err| {
+            let span = err.span.unwrap();
+            format!("{} AT {}:{}", err.kind, span.line, span.col)
+        })
+    }
+
+    const LOOPS: &str = "
+        DEFINE pairs := FUNCTION[] {
+            res := []
+            outer: FOR i := 1 TO 4 {
+                j := 0
+                WHILE TRUE {
+                    j = j + 1
+                    IF j > i { CONTINUE outer }
+                    IF i == 3 { BREAK outer }
+                    IF j == 2 { CONTINUE }
+                    push(res, (i, j))
+                }
+            }
+            RETURN res
+        }
+
+        DEFINE inner_break := FUNCTION[] {
+            res := []
+            FOR i := 1 TO 3 {
+                FOR j := 1 TO 3 {
+                    IF j == 2 { BREAK }
+                    push(res, (i, j))
+                }
+            }
+            RETURN res
+        }
+
+        DEFINE down := FUNCTION[from, to, step] {
+            res := []
+            FOR i := from TO to STEP step {
+                push(res, i)
+            }
+            RETURN res
+        }
+
+        DEFINE captured := FUNCTION[] {
+            fs := []
+            FOR i := 1 TO 3 {
+                push(fs, FUNCTION[] { RETURN i })
+            }
+            RETURN [fs[0](), fs[1](), fs[2]()]
+        }
+
+        DEFINE last := FUNCTION[] {
+            FOR i := 170141183460469231731687303715884105726 TO 170141183460469231731687303715884105727 {
+                x := i
+            }
+            RETURN UNIT
+        }
+    ";
+
+    #[test]
+    fn labels_pick_the_loop_they_leave() {
+        assert_eq!(eval(LOOPS, "pairs()"), Ok("[(1, 1), (2, 1)]".to_string()));
+        assert_eq!(eval(LOOPS, "inner_break()"), Ok("[(1, 1), (2, 1), (3, 1)]".to_string()));
+    }
+
+    #[test]
+    fn steps_count_down_and_stop_at_the_end() {
+        assert_eq!(eval(LOOPS, "down(10, 1, -3)"), Ok("[10, 7, 4, 1]".to_string()));
+        assert_eq!(eval(LOOPS, "down(1, 10, 4)"), Ok("[1, 5, 9]".to_string()));
+        assert_eq!(eval(LOOPS, "down(1, 10, -1)"), Ok("[]".to_string()));
+        assert_eq!(eval(LOOPS, "down(3, 3, 1)"), Ok("[3]".to_string()));
+    }
+
+    #[test]
+    fn a_zero_step_is_an_error() {
+        assert_eq!(eval(LOOPS, "down(1, 10, 0)"), Err("FOR STEP MUST NOT BE ZERO AT 30:38".to_string()));
+    }
+
+    #[test]
+    fn each_iteration_has_its_own_counter() {
+        assert_eq!(eval(LOOPS, "captured()"), Ok("[1, 2, 3]".to_string()));
+    }
+
+    #[test]
+    fn counting_up_to_the_largest_int_stops() {
+        assert_eq!(eval(LOOPS, "last()"), Ok("UNIT".to_string()));
+    }
 }
@@ -0,0 +1,320 @@
+use crate::core::{Expr, Stmt, Bindings, Block, Flow};
+use crate::records::Record;
+use crate::value::Value;
+use crate::error::{Error, ErrorKind, Result};
+use crate::span::Span;
+use std::fmt::Write;
+use std::rc::Rc;
+
+// Declared by a UNION definition, each variant is a record type that points back here
+pub struct Union {
+    name: String,
+    variants: Vec<String>,
+    span: Span
+}
+
+impl Union {
+    pub fn new(name: String, variants: Vec<String>, span: Span) -> Union {
+        Union { name, variants, span }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn variants(&self) -> &[String] {
+        &self.variants
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+// A variant name, with the names its fields are bound to in declared order.
+// Without the names the payload is not bound at all, and _ skips a single field
+pub struct Pattern {
+    variant: String,
+    names: Option<Vec<String>>,
+    span: Span
+}
+
+impl Pattern {
+    pub fn new(variant: String, names: Option<Vec<String>>, span: Span) -> Pattern {
+        Pattern { variant, names, span }
+    }
+
+    pub fn variant(&self) -> &str {
+        &self.variant
+    }
+
+    fn bind(&self, record: &Record, bindings: &mut Bindings) -> Result<()> {
+        let Some(names) = &self.names else {
+            return Ok(());
+        };
+
+        let fields: Vec<Value> = record.fields().map(|(_, value)| value).collect();
+        if names.len() != fields.len() {
+            let kind = ErrorKind::UnpackMismatch { expected: names.len(), found: fields.len() };
+            return Err(Error::new(kind, &self.span));
+        }
+
+        for (name, value) in names.iter().zip(fields) {
+            if name != "_" {
+                bindings.add(name.clone(), value).map_err(|err| err.at(&self.span))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn string(&self) -> String {
+        match &self.names {
+            Some(names) => format!("{}[{}]", self.variant, names.join(", ")),
+            None => self.variant.clone()
+        }
+    }
+}
+
+// Picks the arm for a value, or None when only the ELSE arm fits. Without an ELSE arm
+// the arms have to cover every variant of the value's union, whichever one it is
+fn select<'a, A>(arms: &'a [(Pattern, A)], otherwise: bool, value: Value, span: &Span) -> Result<Option<(&'a Pattern, &'a A, Rc<Record>)>> {
+    let record = match value {
+        Value::Record(record) => record,
+        _ if otherwise => return Ok(None),
+        value => return Err(Error::new(ErrorKind::TypeMismatch { expected: "RECORD", found: value.type_name() }, span))
+    };
+
+    if let (Some(union), false) = (record.kind().union(), otherwise) {
+        let missing = union.variants.iter().find(|variant| !arms.iter().any(|(pattern, _)| pattern.variant == **variant));
+        if let Some(variant) = missing {
+            let kind = ErrorKind::NonExhaustive { union: union.name.as_str().into(), variant: variant.clone() };
+            return Err(Error::new(kind, span));
+        }
+    }
+
+    match arms.iter().find(|(pattern, _)| pattern.variant == record.kind().name()) {
+        Some((pattern, arm)) => Ok(Some((pattern, arm, record))),
+        None if otherwise => Ok(None),
+        None => Err(Error::new(ErrorKind::NoMatch(record.kind().name().to_string()), span))
+    }
+}
+
+fn indent(text: &str) -> String {
+    let mut res = String::new();
+
+    for line in text.lines() {
+        writeln!(&mut res, "    {line}").unwrap();
+    }
+
+    res
+}
+
+pub struct MatchStmt<E: Expr> {
+    subject: E,
+    arms: Vec<(Pattern, Block)>,
+    otherwise: Option<Block>,
+    span: Span
+}
+
+impl<E: Expr> MatchStmt<E> {
+    pub fn new(subject: E, arms: Vec<(Pattern, Block)>, otherwise: Option<Block>, span: Span) -> MatchStmt<E> {
+        MatchStmt { subject, arms, otherwise, span }
+    }
+}
+
+impl<E: Expr> Stmt for MatchStmt<E> {
+    fn execute(&self, bindings: &mut Bindings) -> Result<Flow> {
+        let value = self.subject.value(bindings)?;
+
+        match select(&self.arms, self.otherwise.is_some(), value, self.subject.span())? {
+            Some((pattern, block, record)) => {
+                bindings.new_frame();
+                let res = pattern.bind(&record, bindings).and_then(|_| block.execute(bindings));
+                bindings.pop_frame();
+
+                res
+            }
+            None => self.otherwise.as_ref().map_or(Ok(Flow::Next), |block| block.execute(bindings))
+        }
+    }
+
+    fn string(&self) -> String {
+        let mut arms = String::new();
+
+        for (pattern, block) in &self.arms {
+            writeln!(&mut arms, "{} {}", pattern.string(), block.string()).unwrap();
+        }
+
+        if let Some(block) = &self.otherwise {
+            writeln!(&mut arms, "ELSE {}", block.string()).unwrap();
+        }
+
+        format!("MATCH {} {{\n{}}}", self.subject.string(), indent(&arms))
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+pub struct MatchExpr<E: Expr> {
+    subject: E,
+    arms: Vec<(Pattern, Rc<dyn Expr>)>,
+    otherwise: Option<Rc<dyn Expr>>,
+    span: Span
+}
+
+impl<E: Expr> MatchExpr<E> {
+    pub fn new(subject: E, arms: Vec<(Pattern, Rc<dyn Expr>)>, otherwise: Option<Rc<dyn Expr>>, span: Span) -> MatchExpr<E> {
+        MatchExpr { subject, arms, otherwise, span }
+    }
+
+    // Evaluates the chosen arm with eval, inside the frame its pattern binds
+    fn arm<R>(&self, bindings: &mut Bindings, eval: impl Fn(&Rc<dyn Expr>, &mut Bindings) -> Result<R>) -> Result<R> {
+        let value = self.subject.value(bindings)?;
+
+        match select(&self.arms, self.otherwise.is_some(), value, self.subject.span())? {
+            Some((pattern, expr, record)) => {
+                bindings.new_frame();
+                let res = pattern.bind(&record, bindings).and_then(|_| eval(expr, bindings));
+                bindings.pop_frame();
+
+                res
+            }
+            // select only gives None when there is an ELSE arm
+            None => eval(self.otherwise.as_ref().unwrap(), bindings)
+        }
+    }
+}
+
+impl<E: Expr> Expr for MatchExpr<E> {
+    fn value(&self, bindings: &mut Bindings) -> Result<Value> {
+        self.arm(bindings, |expr, bindings| expr.value(bindings))
+    }
+
+    fn tail(&self, bindings: &mut Bindings) -> Result<Flow> {
+        self.arm(bindings, |expr, bindings| expr.tail(bindings))
+    }
+
+    fn string(&self) -> String {
+        let mut arms: Vec<String> = self.arms.iter()
+            .map(|(pattern, expr)| format!("{} THEN {}", pattern.string(), expr.string()))
+            .collect();
+
+        if let Some(expr) = &self.otherwise {
+            arms.push(format!("ELSE {}", expr.string()));
+        }
+
+        format!("MATCH {} {{{}}}", self.subject.string(), arms.join(", "))
+    }
+
+    fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Program;
+    use crate::parser::{parse, Parser};
+
+    const SHAPES: &str = "
+        UNION Shape[Circle[radius], Rect[w, h], Empty]
+        RECORD Point[x, y]
+    ";
+
+    // Each source is parsed on its own, as REPL inputs are, so a MATCH on a union from an
+    // earlier one is only checked when it runs
+    fn eval(sources: &[&str], expr: &str) -> Result<String, String> {
+        let mut program = Program::new();
+        for source in sources {
+            for def in parse(source, "test").unwrap() {
+                program.add(def);
+            }
+        }
+        program.load().unwrap();
+
+        let expr = Parser::new(expr, "test").unwrap().parse_expr().unwrap();
+        program.eval(&expr).map(|value| value.string()).map_err(|err| err.kind.to_string())
+    }
+
+    fn parse_error(source: &str) -> (String, usize, usize) {
+        let err = parse(source, "test").err().unwrap();
+        (err.message, err.span.line, err.span.col)
+    }
+
+    const AREA: &str = "
+        DEFINE area := FUNCTION[s] {
+            RETURN MATCH s {
+                Circle[r] THEN 3 * r * r,
+                Rect[_, h] THEN h,
+                Empty THEN 0
+            }
+        }
+    ";
+
+    #[test]
+    fn arms_bind_fields_in_order_and_underscore_skips_one() {
+        assert_eq!(eval(&[SHAPES, AREA], "area(NEW Circle {radius: 2})"), Ok("12".to_string()));
+        assert_eq!(eval(&[SHAPES, AREA], "area(NEW Rect {h: 5, w: 2})"), Ok("5".to_string()));
+        assert_eq!(eval(&[SHAPES, AREA], "area(NEW Empty {})"), Ok("0".to_string()));
+    }
+
+    #[test]
+    fn a_missing_variant_is_a_parse_error_when_the_union_is_in_view() {
+        let source = "
+            UNION Shape[Circle[radius], Rect[w, h], Empty]
+            DEFINE f := FUNCTION[s] {
+                RETURN MATCH s { Circle THEN 1, Empty THEN 0 }
+            }
+        ";
+        assert_eq!(parse_error(source), ("MATCH ON Shape MISSES Rect".to_string(), 4, 24));
+
+        let source = "
+            UNION Shape[Circle[radius], Empty]
+            DEFINE f := FUNCTION[s] {
+                RETURN MATCH s { Circle THEN 1, Square THEN 2, ELSE 0 }
+            }
+        ";
+        assert_eq!(parse_error(source), ("Square IS NOT A VARIANT OF Shape".to_string(), 4, 24));
+    }
+
+    #[test]
+    fn a_missing_variant_fails_at_runtime_otherwise() {
+        let partial = "
+            DEFINE f := FUNCTION[s] {
+                RETURN MATCH s { Circle THEN 1, Empty THEN 0 }
+            }
+        ";
+        // Whichever variant the value is, the arms must cover them all
+        assert_eq!(eval(&[SHAPES, partial], "f(NEW Circle {radius: 1})"), Err("MATCH ON Shape MISSES Rect".to_string()));
+        assert_eq!(eval(&[SHAPES, partial], "f(NEW Point {x: 1, y: 2})"), Err("NO MATCH ARM FOR Point".to_string()));
+        assert_eq!(eval(&[SHAPES, partial], "f(1)"), Err("EXPECTED RECORD, FOUND INT".to_string()));
+    }
+
+    #[test]
+    fn else_takes_whatever_the_arms_do_not() {
+        let otherwise = "
+            DEFINE f := FUNCTION[s] {
+                RETURN MATCH s { Circle THEN 1, ELSE 0 }
+            }
+        ";
+        assert_eq!(eval(&[SHAPES, otherwise], "f(NEW Rect {w: 1, h: 2})"), Ok("0".to_string()));
+        assert_eq!(eval(&[SHAPES, otherwise], "f(NEW Point {x: 1, y: 2})"), Ok("0".to_string()));
+        assert_eq!(eval(&[SHAPES, otherwise], "f(1)"), Ok("0".to_string()));
+    }
+
+    #[test]
+    fn a_pattern_names_every_field() {
+        let short = "
+            DEFINE f := FUNCTION[s] {
+                MATCH s {
+                    Rect[w] { RETURN w }
+                    ELSE { RETURN 0 }
+                }
+            }
+        ";
+        assert_eq!(eval(&[SHAPES, short], "f(NEW Rect {w: 1, h: 2})"), Err("CANNOT UNPACK 2 VALUES INTO 1 NAMES".to_string()));
+    }
+}
@@ -0,0 +1,375 @@
+use crate::core::{Expr, Frame};
+use crate::expressions::{Function, Builtin};
+use crate::records::{RecordType, Record};
+use crate::bigint::BigInt;
+use crate::error::{ErrorKind, Result};
+use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Where a cell was when a pointer to it was made. Reads and writes go there, wherever the
+// pointer ends up, and pointers are equal when they point at the same place
+#[derive(Clone)]
+pub enum Slot {
+    Var(Frame, String),
+    Global(Frame, String),
+    Item(Rc<RefCell<Vec<Value>>>, usize),
+    Part(Rc<[Value]>, usize),
+    Entry(Rc<RefCell<Map>>, Key),
+    Field(Rc<Record>, usize)
+}
+
+// Errors have no span, the cell or dereference puts its own on them
+impl Slot {
+    // A list can shrink and an entry can be removed after the pointer is made
+    pub fn get(&self) -> Result<Value> {
+        match self {
+            Slot::Var(frame, name) | Slot::Global(frame, name) => {
+                frame.get(name).ok_or_else(|| ErrorKind::UnboundVariable(name.clone()).into())
+            }
+            Slot::Item(list, index) => {
+                let list = list.borrow();
+                let item = list.get(*index).ok_or(ErrorKind::IndexOutOfRange { index: *index as i128, len: list.len() })?;
+                Ok(item.clone())
+            }
+            Slot::Part(items, index) => Ok(items[*index].clone()),
+            Slot::Entry(map, key) => {
+                let value = map.borrow().get(key).cloned();
+                value.ok_or_else(|| ErrorKind::MissingKey(key.value().string()).into())
+            }
+            Slot::Field(record, index) => Ok(record.field(*index))
+        }
+    }
+
+    // Map entries may be new, list items have to still be there
+    pub fn set(&self, value: Value) -> Result<()> {
+        match self {
+            Slot::Var(frame, name) => frame.set(name, value),
+            Slot::Global(_, name) => return Err(ErrorKind::UnboundVariable(name.clone()).into()),
+            Slot::Item(list, index) => {
+                let mut list = list.borrow_mut();
+                let len = list.len();
+                let item = list.get_mut(*index).ok_or(ErrorKind::IndexOutOfRange { index: *index as i128, len })?;
+                *item = value;
+            }
+            Slot::Part(..) => return Err(ErrorKind::Immutable("TUPLE").into()),
+            Slot::Entry(map, key) => map.borrow_mut().insert(key.clone(), value),
+            Slot::Field(record, index) => record.set_field(*index, value)
+        }
+
+        Ok(())
+    }
+
+    pub fn same(&self, other: &Slot) -> bool {
+        match (self, other) {
+            (Slot::Var(x, a), Slot::Var(y, b)) | (Slot::Global(x, a), Slot::Global(y, b)) => x.same(y) && a == b,
+            (Slot::Item(x, i), Slot::Item(y, j)) => Rc::ptr_eq(x, y) && i == j,
+            (Slot::Part(x, i), Slot::Part(y, j)) => Rc::ptr_eq(x, y) && i == j,
+            (Slot::Entry(x, a), Slot::Entry(y, b)) => Rc::ptr_eq(x, y) && a == b,
+            (Slot::Field(x, i), Slot::Field(y, j)) => Rc::ptr_eq(x, y) && i == j,
+            _ => false
+        }
+    }
+}
+
+// Made by &, which keeps the text of the cell to show the pointer by
+pub struct Pointer {
+    slot: Slot,
+    name: String
+}
+
+impl Pointer {
+    pub fn new(slot: Slot, name: String) -> Pointer {
+        Pointer { slot, name }
+    }
+
+    pub fn slot(&self) -> &Slot {
+        &self.slot
+    }
+}
+
+// The values a map can be keyed by, floats are left out since they do not compare reliably
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Int(i128),
+    Big(Rc<BigInt>),
+    Text(Rc<str>),
+    Bool(bool)
+}
+
+impl Key {
+    pub fn new(value: &Value) -> Result<Key> {
+        match value {
+            Value::Int(n) => Ok(Key::Int(*n)),
+            Value::Big(n) => Ok(Key::Big(Rc::clone(n))),
+            Value::Text(s) => Ok(Key::Text(Rc::clone(s))),
+            Value::Bool(b) => Ok(Key::Bool(*b)),
+            value => Err(ErrorKind::InvalidKey(value.type_name()).into())
+        }
+    }
+
+    pub fn value(&self) -> Value {
+        match self {
+            Key::Int(n) => Value::Int(*n),
+            Key::Big(n) => Value::Big(Rc::clone(n)),
+            Key::Text(s) => Value::Text(Rc::clone(s)),
+            Key::Bool(b) => Value::Bool(*b)
+        }
+    }
+}
+
+// Entries stay in the order their keys were first inserted, so iterating is deterministic
+#[derive(Clone, Default)]
+pub struct Map {
+    entries: Vec<(Key, Value)>,
+    positions: HashMap<Key, usize>
+}
+
+impl Map {
+    pub fn new() -> Map {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.positions.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn insert(&mut self, key: Key, value: Value) {
+        match self.positions.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        let i = self.positions.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+
+        for position in self.positions.values_mut() {
+            if *position > i {
+                *position -= 1;
+            }
+        }
+
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Key, Value)> {
+        self.entries.iter()
+    }
+}
+
+// What expressions evaluate to, kept apart from the nodes that produce them
+#[derive(Clone)]
+pub enum Value {
+    Int(i128),
+    // Only ever holds ints that do not fit in an i128, see Value::big
+    Big(Rc<BigInt>),
+    Float(f64),
+    Bool(bool),
+    Text(Rc<str>),
+    // Shared, so every copy of a list sees changes made through any of them
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Map>>),
+    // Fixed once built, unlike lists
+    Tuple(Rc<[Value]>),
+    RecordType(Rc<RecordType>),
+    Record(Rc<Record>),
+    Function(Rc<Function>),
+    Builtin(Rc<Builtin>),
+    Pointer(Rc<Pointer>),
+    Unit
+}
+
+impl Value {
+    // Big ints that fit go back to being plain ints, so each int has a single form
+    pub fn big(n: BigInt) -> Value {
+        match n.to_i128() {
+            Some(n) => Value::Int(n),
+            None => Value::Big(Rc::new(n))
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) | Value::Big(_) => "INT",
+            Value::Float(_) => "FLOAT",
+            Value::Bool(_) => "BOOL",
+            Value::Text(_) => "TEXT",
+            Value::List(_) => "LIST",
+            Value::Map(_) => "MAP",
+            Value::Tuple(_) => "TUPLE",
+            Value::RecordType(_) => "TYPE",
+            Value::Record(_) => "RECORD",
+            Value::Function(_) => "FUNCTION",
+            Value::Builtin(_) => "BUILTIN",
+            Value::Pointer(_) => "POINTER",
+            Value::Unit => "UNIT"
+        }
+    }
+
+    pub fn string(&self) -> String {
+        self.show(&mut Vec::new())
+    }
+
+    // Lists, maps and records can hold themselves, so the ones already being shown further
+    // up are cut short as [...], {...} and Name {...}
+    pub(crate) fn show(&self, seen: &mut Vec<usize>) -> String {
+        match self {
+            Value::Int(n) => format!("{n}"),
+            Value::Big(n) => format!("{n}"),
+            // Debug is the shortest text that reads back as the same float and always marks it as one
+            Value::Float(n) if n.is_nan() => "NAN".to_string(),
+            Value::Float(n) if n.is_infinite() => if *n > 0.0 { "INF" } else { "-INF" }.to_string(),
+            Value::Float(n) => format!("{n:?}"),
+            Value::Bool(b) => if *b { "TRUE" } else { "FALSE" }.to_string(),
+            Value::Text(s) => format!("{s:?}"),
+            Value::List(list) if seen.contains(&address(list)) => "[...]".to_string(),
+            Value::List(list) => {
+                seen.push(address(list));
+                let items: Vec<String> = list.borrow().iter().map(|item| item.show(seen)).collect();
+                seen.pop();
+                format!("[{}]", items.join(", "))
+            }
+            // A lone item keeps its comma so it does not read as a bracketed value
+            Value::Tuple(items) if items.len() == 1 => format!("({},)", items[0].show(seen)),
+            Value::Tuple(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.show(seen)).collect();
+                format!("({})", items.join(", "))
+            }
+            Value::Map(map) if seen.contains(&address(map)) => "{...}".to_string(),
+            Value::Map(map) => {
+                seen.push(address(map));
+                let entries: Vec<String> = map.borrow().iter()
+                    .map(|(key, value)| format!("{}: {}", key.value().string(), value.show(seen)))
+                    .collect();
+                seen.pop();
+                format!("{{{}}}", entries.join(", "))
+            }
+            Value::RecordType(kind) => kind.string(),
+            Value::Record(record) => record.show(seen),
+            Value::Function(function) => function.string(),
+            Value::Builtin(builtin) => builtin.string(),
+            Value::Pointer(pointer) => format!("<POINTER TO {}>", pointer.name),
+            Value::Unit => "UNIT".to_string()
+        }
+    }
+
+    // Pairs of lists, maps or records already being compared further up count as equal, so
+    // two values that hold themselves in the same places compare equal instead of forever
+    fn equal(&self, other: &Value, seen: &mut Vec<(usize, usize)>) -> bool {
+        let pair = match (self, other) {
+            (Value::List(x), Value::List(y)) => (address(x), address(y)),
+            (Value::Map(x), Value::Map(y)) => (address(x), address(y)),
+            (Value::Record(x), Value::Record(y)) => (address(x), address(y)),
+            (Value::Tuple(x), Value::Tuple(y)) => {
+                return x.len() == y.len() && x.iter().zip(y.iter()).all(|(x, y)| x.equal(y, seen));
+            }
+            _ => return self == other
+        };
+
+        if pair.0 == pair.1 || seen.contains(&pair) {
+            return true;
+        }
+
+        seen.push(pair);
+        let res = match (self, other) {
+            (Value::List(x), Value::List(y)) => {
+                let (x, y) = (x.borrow(), y.borrow());
+                x.len() == y.len() && x.iter().zip(y.iter()).all(|(x, y)| x.equal(y, seen))
+            }
+            (Value::Map(x), Value::Map(y)) => {
+                let (x, y) = (x.borrow(), y.borrow());
+                x.len() == y.len() && x.iter().all(|(key, value)| y.get(key).is_some_and(|other| value.equal(other, seen)))
+            }
+            (Value::Record(x), Value::Record(y)) => {
+                let same_kind = x.kind().name() == y.kind().name() && x.kind().span() == y.kind().span();
+                same_kind && x.fields().zip(y.fields()).all(|((_, x), (_, y))| x.equal(&y, seen))
+            }
+            _ => unreachable!()
+        };
+        seen.pop();
+
+        res
+    }
+}
+
+// Tells shared values apart by where they live
+fn address<T: ?Sized>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc) as *const () as usize
+}
+
+// Ints and floats compare by number, lists and tuples by their items, maps by their
+// entries in any order, records by their type and fields, other kinds are never equal
+// to each other, callables, types and pointers compare by what they refer to
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(x), Value::Int(y)) => x == y,
+            (Value::Float(x), Value::Float(y)) => x == y,
+            (Value::Int(x), Value::Float(y)) | (Value::Float(y), Value::Int(x)) => BigInt::from(*x).cmp_f64(*y) == Some(Ordering::Equal),
+            (Value::Big(x), Value::Big(y)) => x == y,
+            (Value::Big(x), Value::Float(y)) | (Value::Float(y), Value::Big(x)) => x.cmp_f64(*y) == Some(Ordering::Equal),
+            (Value::Bool(x), Value::Bool(y)) => x == y,
+            (Value::Text(x), Value::Text(y)) => x == y,
+            (Value::List(_), Value::List(_)) | (Value::Tuple(_), Value::Tuple(_)) => self.equal(other, &mut Vec::new()),
+            (Value::Map(_), Value::Map(_)) | (Value::Record(_), Value::Record(_)) => self.equal(other, &mut Vec::new()),
+            (Value::RecordType(x), Value::RecordType(y)) => x.name() == y.name() && x.span() == y.span(),
+            (Value::Function(x), Value::Function(y)) => x.same(y),
+            (Value::Builtin(x), Value::Builtin(y)) => x.name() == y.name(),
+            (Value::Pointer(x), Value::Pointer(y)) => x.slot.same(&y.slot),
+            (Value::Unit, Value::Unit) => true,
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::Program;
+    use crate::parser::{parse, Parser};
+
+    fn eval(source: &str, expr: &str) -> String {
+        let mut program = Program::new();
+        for def in parse(source, "test").unwrap() {
+            program.add(def);
+        }
+        program.load().unwrap();
+
+        let expr = Parser::new(expr, "test").unwrap().parse_expr().unwrap();
+        program.eval(&expr).unwrap().string()
+    }
+
+    const COUNTER: &str = "
+        DEFINE counter := FUNCTION[] {
+            n := 0
+            RETURN FUNCTION[] {
+                n = n + 1
+                RETURN n
+            }
+        }
+
+        DEFINE a := counter()
+        DEFINE b := counter()
+        DEFINE c := a
+    ";
+
+    #[test]
+    fn closures_from_separate_calls_differ() {
+        assert_eq!(eval(COUNTER, "a == b"), "FALSE");
+        assert_eq!(eval(COUNTER, "a == c"), "TRUE");
+        assert_eq!(eval(COUNTER, "counter == counter"), "TRUE");
+    }
+}